@@ -1,6 +1,6 @@
 use axum::{
     Router, middleware,
-    routing::{get, post},
+    routing::{delete, get, post, put},
 };
 
 use authentication::middle::Authenticate;
@@ -17,7 +17,48 @@ pub fn router(state: AppState) -> Router<AppState> {
         .route("/account/notification", post(users::notification))
         .route("/account/favourites", get(users::favourites))
         .route("/account/favourite", post(users::toggle_favourite))
+        .route(
+            "/account/alerts",
+            get(users::get_alerts).post(users::create_alert),
+        )
+        .route("/account/alerts/seen", post(users::dismiss_alerts))
+        .route("/account/alerts/{id}", delete(users::delete_alert))
+        .route(
+            "/account/lists",
+            get(users::get_lists).post(users::create_list),
+        )
+        .route(
+            "/account/lists/{id}",
+            put(users::rename_list).delete(users::delete_list),
+        )
+        .route("/account/lists/{id}/entries", post(users::put_entry))
+        .route(
+            "/account/lists/{id}/entries/{index}",
+            delete(users::delete_entry),
+        )
+        .route("/account/lists/{id}/move", post(users::move_entry))
+        .route(
+            "/account/lists/{id}/share",
+            post(users::share_list).delete(users::unshare_list),
+        )
+        .route("/account/password", post(users::change_password))
+        .route("/account/username", post(users::change_username))
+        .route("/account/email", post(users::change_email))
+        .route("/account/export", get(users::export))
         .route("/account/delete", post(users::delete))
+        .route(
+            "/account/sessions",
+            get(users::get_sessions).delete(users::revoke_other_sessions),
+        )
+        .route("/account/sessions/{id}", delete(users::revoke_session))
+        .route(
+            "/account/searches",
+            get(users::get_searches).post(users::create_search),
+        )
+        .route(
+            "/account/searches/{id}",
+            put(users::update_search).delete(users::delete_search),
+        )
         .layer(middleware::from_extractor_with_state::<
             Authenticate,
             AppState,
@@ -26,6 +67,9 @@ pub fn router(state: AppState) -> Router<AppState> {
     Router::<AppState>::new()
         .merge(protected)
         .route("/data/preview", get(products::get_preview))
+        .route("/data/products", get(products::get_products))
+        .route("/data/product/{index}", get(products::get_product))
+        .route("/data/product/{index}/history", get(products::get_history))
         .route("/data/image/{index}", get(products::get_image))
         .route("/data/stores", get(metadata::get_stores))
         .route("/data/countries", get(metadata::get_countries))
@@ -9,7 +9,7 @@ pub async fn get_stores(
     let taxfree = subdomain.is_taxfree();
     let field: &str = if taxfree { "taxfree.stores" } else { "stores" };
 
-    let stores: Vec<String> = database::metadata::get_distinct(&state.db, field, taxfree).await;
+    let stores: Vec<String> = state.products.get_distinct(field, taxfree).await;
 
     Ok(Json(stores))
 }
@@ -18,8 +18,10 @@ pub async fn get_countries(
     State(state): State<AppState>,
     subdomain: Subdomain,
 ) -> Result<Json<Vec<String>>, AppError> {
-    let countries: Vec<String> =
-        database::metadata::get_distinct(&state.db, &"country", subdomain.is_taxfree()).await;
+    let countries: Vec<String> = state
+        .products
+        .get_distinct("country", subdomain.is_taxfree())
+        .await;
 
     Ok(Json(countries))
 }
@@ -1,14 +1,20 @@
-use axum::{Json, extract::{Path, State}, http::header, response::IntoResponse};
-use mongodb::Database;
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::header,
+    response::IntoResponse,
+};
 use regex::Regex;
 use serde::Serialize;
-use std::env;
 use std::path::PathBuf;
 use std::sync::LazyLock;
 use tokio::fs;
 
-use database;
-use shared::{errors::AppError, models::Product};
+use authentication::middle::MaybeAuthenticate;
+use shared::{
+    errors::AppError, history::PriceHistory, models::Product, query::Parameters, state::AppState,
+    subdomain::Subdomain,
+};
 
 static RE_INDEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[0-9]+$").unwrap());
 
@@ -18,26 +24,106 @@ pub struct PreviewResponse {
     tax: Option<Product>,
 }
 
-pub async fn get_preview(State(db): State<Database>) -> Json<PreviewResponse> {
+pub async fn get_preview(State(state): State<AppState>) -> Json<PreviewResponse> {
     let (vmp, tax) = tokio::join!(
-        database::products::get_preview(&db, false),
-        database::products::get_preview(&db, true),
+        state.products.get_preview(false),
+        state.products.get_preview(true),
     );
     Json(PreviewResponse { vmp, tax })
 }
 
-pub async fn get_image(Path(index): Path<String>) -> Result<impl IntoResponse, AppError> {
+#[derive(Serialize)]
+pub struct ProductsResponse {
+    products: Vec<Product>,
+    page: i64,
+    max_page: u64,
+    total: u64,
+}
+
+pub async fn get_products(
+    State(state): State<AppState>,
+    subdomain: Subdomain,
+    Query(mut parameters): Query<Parameters>,
+    MaybeAuthenticate(user): MaybeAuthenticate,
+) -> Result<Json<ProductsResponse>, AppError> {
+    if matches!(subdomain, Subdomain::Landing) {
+        return Err(AppError::BadRequest("Ugyldig subdomene.".to_string()));
+    }
+
+    let prices_updated = state.metadata.get_prices_updated(subdomain.name()).await;
+    let page =
+        database::products::get_listing(&state, &mut parameters, &subdomain, &user, prices_updated)
+            .await?;
+
+    Ok(Json(ProductsResponse {
+        products: page.items,
+        page: parameters.page(),
+        max_page: page.pages,
+        total: page.total,
+    }))
+}
+
+fn parse_index(index: &str) -> Result<i64, AppError> {
+    if !RE_INDEX.is_match(index) {
+        return Err(AppError::BadRequest("Ugyldig index.".to_string()));
+    }
+    index
+        .parse()
+        .map_err(|_| AppError::BadRequest("Ugyldig index.".to_string()))
+}
+
+pub async fn get_product(
+    State(state): State<AppState>,
+    Path(index): Path<String>,
+) -> Result<Json<Product>, AppError> {
+    let index = parse_index(&index)?;
+
+    state
+        .products
+        .get_product(index)
+        .await
+        .map(Json)
+        .ok_or(AppError::NotFound)
+}
+
+pub async fn get_history(
+    State(state): State<AppState>,
+    Path(index): Path<String>,
+) -> Result<Json<PriceHistory>, AppError> {
+    let index = parse_index(&index)?;
+
+    let product = state
+        .products
+        .get_product(index)
+        .await
+        .ok_or(AppError::NotFound)?;
+
+    Ok(Json(PriceHistory::from_product(
+        &product,
+        chrono::Local::now().date_naive(),
+    )))
+}
+
+pub async fn get_image(
+    State(state): State<AppState>,
+    Path(index): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
     if !RE_INDEX.is_match(&index) {
         return Err(AppError::BadRequest("Ugyldig index.".to_string()));
     }
 
-    let image_dir = env::var("IMAGE_DIR").map_err(|_| AppError::InternalServerError)?;
+    let image_dir = state
+        .config
+        .image_dir
+        .as_ref()
+        .ok_or(AppError::InternalServerError)?;
 
-    let file_path = PathBuf::from(&image_dir).join(format!("{}.png", index));
-    if file_path.exists() && file_path.is_file() {
-        if let Ok(contents) = fs::read(&file_path).await {
-            return Ok(([(header::CONTENT_TYPE, "image/png")], contents));
-        }
+    let file_path = image_dir.join(format!("{}.png", index));
+    if file_path.exists()
+        && file_path.is_file()
+        && let Ok(contents) = fs::read(&file_path).await
+    {
+        return Ok(([(header::CONTENT_TYPE, "image/png")], contents));
     }
 
     Ok(serve_fallback().await)
@@ -61,4 +147,13 @@ mod tests {
         assert!(!RE_INDEX.is_match("12a34"));
         assert!(!RE_INDEX.is_match("-1"));
     }
+
+    #[test]
+    fn parse_index_rejects_overflowing_digits() {
+        assert_eq!(parse_index("12345").unwrap(), 12345);
+        assert!(matches!(
+            parse_index("99999999999999999999"),
+            Err(AppError::BadRequest(_))
+        ));
+    }
 }
@@ -1,21 +1,41 @@
 use authentication::middle::Authenticate;
-use axum::{Json, extract::State};
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::header,
+    response::IntoResponse,
+};
 use axum_extra::extract::cookie::{Cookie, CookieJar};
+use mongodb::bson::{DateTime, oid::ObjectId};
+use serde::Serialize;
+use std::collections::HashMap;
 
-use database::users;
-use authentication::middle::verify_password;
+use authentication::{
+    auth::{ensure_email_available, send_verification, validate_email},
+    middle::{hash_password, new_token, validate_password, verify_password},
+};
 use shared::{
     errors::AppError,
-    models::{Index, Notify, User},
-    query::DeleteRequest,
+    models::{
+        Alert, FavouriteEntry, FavouriteList, Index, MAX_ALERTS, MAX_FAVOURITE_LISTS,
+        MAX_LIST_ENTRIES, MAX_NOTE_LENGTH, MAX_SAVED_SEARCHES, Notify, Product, SavedSearch,
+        Session, User,
+    },
+    query::{
+        AlertRequest, ChangeEmailRequest, ChangePasswordRequest, ChangeUsernameRequest,
+        DeleteRequest, ListEntryRequest, ListRequest, MoveEntryRequest, SavedSearchRequest,
+    },
     state::AppState,
+    subdomain::Subdomain,
 };
 
 pub async fn get_user(
     State(state): State<AppState>,
     auth: Authenticate,
 ) -> Result<Json<User>, AppError> {
-    let user: User = users::get_user_by_id(&state.db, &auth.id)
+    let user: User = state
+        .users
+        .get_user_by_id(&auth.id)
         .await
         .ok_or(AppError::NotFound)?;
     Ok(Json(user))
@@ -26,7 +46,7 @@ pub async fn logout(
     jar: CookieJar,
     auth: Authenticate,
 ) -> Result<(CookieJar, Json<String>), AppError> {
-    users::logout(&state.db, &auth.session_id).await?;
+    state.sessions.delete_session(&auth.session_id).await?;
     let jar = jar.remove(Cookie::from("session_id"));
     Ok((jar, Json("ok".to_string())))
 }
@@ -36,7 +56,7 @@ pub async fn notification(
     auth: Authenticate,
     Json(payload): Json<Notify>,
 ) -> Result<Json<String>, AppError> {
-    users::notification(&state.db, &auth.id, payload.notify).await?;
+    state.users.notification(&auth.id, payload.notify).await?;
     Ok(Json("ok".to_string()))
 }
 
@@ -44,7 +64,7 @@ pub async fn favourites(
     State(state): State<AppState>,
     auth: Authenticate,
 ) -> Result<Json<Vec<i64>>, AppError> {
-    let favourites: Vec<i64> = users::favourites(&state.db, &auth.id).await?;
+    let favourites: Vec<i64> = state.users.favourites(&auth.id).await?;
     Ok(Json(favourites))
 }
 
@@ -53,7 +73,10 @@ pub async fn toggle_favourite(
     auth: Authenticate,
     Json(payload): Json<Index>,
 ) -> Result<Json<String>, AppError> {
-    users::toggle_favourite(&state.db, &auth.id, &payload.index).await?;
+    state
+        .users
+        .toggle_favourite(&auth.id, &payload.index)
+        .await?;
     Ok(Json("ok".to_string()))
 }
 
@@ -62,7 +85,9 @@ pub async fn delete(
     auth: Authenticate,
     Json(payload): Json<DeleteRequest>,
 ) -> Result<Json<String>, AppError> {
-    let user: User = users::get_user_by_id(&state.db, &auth.id)
+    let user: User = state
+        .users
+        .get_user_by_id(&auth.id)
         .await
         .ok_or(AppError::NotFound)?;
 
@@ -70,6 +95,768 @@ pub async fn delete(
         return Err(AppError::Unauthorized);
     }
 
-    users::delete_user(&state.db, &auth.id).await?;
+    state.sessions.delete_sessions_for_user(&auth.id).await?;
+    state.users.delete_user(&auth.id).await?;
+    Ok(Json("ok".to_string()))
+}
+
+fn validate_username(username: &str) -> Result<String, AppError> {
+    let username = username.trim();
+    let length = username.chars().count();
+    if !(2..=32).contains(&length) {
+        return Err(AppError::BadRequest(
+            "Brukernavnet må være mellom 2 og 32 tegn.".to_string(),
+        ));
+    }
+    Ok(username.to_string())
+}
+
+pub async fn change_password(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<Json<String>, AppError> {
+    if !verify_password(&payload.password, &auth.user.password) {
+        return Err(AppError::Unauthorized);
+    }
+    validate_password(&payload.new_password)?;
+
+    let hashed_password =
+        hash_password(&payload.new_password).map_err(|_| AppError::InternalServerError)?;
+    state
+        .users
+        .update_password(&auth.id, &hashed_password)
+        .await?;
+
+    // Other devices must log in again with the new password.
+    state
+        .sessions
+        .delete_other_sessions(&auth.id, &auth.session_id)
+        .await?;
+    Ok(Json("ok".to_string()))
+}
+
+pub async fn change_username(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Json(payload): Json<ChangeUsernameRequest>,
+) -> Result<Json<String>, AppError> {
+    if !verify_password(&payload.password, &auth.user.password) {
+        return Err(AppError::Unauthorized);
+    }
+    let username = validate_username(&payload.username)?;
+
+    if let Some(existing) = state.users.get_user_by_name(&username).await
+        && existing.user_id != auth.id
+    {
+        return Err(AppError::BadRequest(
+            "Brukeren finnes allerede.".to_string(),
+        ));
+    }
+
+    state.users.update_username(&auth.id, &username).await?;
+    Ok(Json("ok".to_string()))
+}
+
+pub async fn change_email(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Json(payload): Json<ChangeEmailRequest>,
+) -> Result<Json<String>, AppError> {
+    if !verify_password(&payload.password, &auth.user.password) {
+        return Err(AppError::Unauthorized);
+    }
+    let email = validate_email(&payload.email)?;
+
+    if email.to_lowercase() == auth.user.email.to_lowercase() {
+        return Ok(Json("ok".to_string()));
+    }
+    ensure_email_available(&state, &email).await?;
+
+    state.users.update_email(&auth.id, &email).await?;
+
+    let mut user = auth.user;
+    user.email = email;
+    if let Err(error) = send_verification(&state, &user).await {
+        tracing::error!(%error, user_id = %user.user_id, "Could not send verification");
+    }
+    Ok(Json("ok".to_string()))
+}
+
+/// Everything stored about a user, as handed out on request. The password hash is left out.
+#[derive(Serialize)]
+pub struct AccountExport {
+    pub exported: String,
+    pub user: ExportedUser,
+    pub sessions: Vec<SessionInfo>,
+    pub favourites: Vec<ExportedFavourite>,
+    pub lists: Vec<ExportedList>,
+    pub alerts: Vec<Alert>,
+    pub preferences: ExportedPreferences,
+}
+
+#[derive(Serialize)]
+pub struct ExportedUser {
+    pub id: String,
+    pub username: String,
+    pub email: String,
+    pub verified: bool,
+}
+
+#[derive(Serialize)]
+pub struct ExportedFavourite {
+    pub index: i64,
+    #[serde(flatten)]
+    pub product: ExportedProduct,
+}
+
+#[derive(Serialize)]
+pub struct ExportedList {
+    pub id: String,
+    pub name: String,
+    pub entries: Vec<ExportedEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slug: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ExportedEntry {
+    #[serde(flatten)]
+    pub entry: FavouriteEntry,
+    #[serde(flatten)]
+    pub product: ExportedProduct,
+}
+
+/// The product behind a favourite or list entry, all `None` when it no longer exists.
+#[derive(Serialize)]
+pub struct ExportedProduct {
+    pub name: Option<String>,
+    pub price: Option<f64>,
+    pub literprice: Option<f64>,
+    pub url: Option<String>,
+}
+
+impl ExportedProduct {
+    fn from_product(product: Option<&Product>) -> Self {
+        ExportedProduct {
+            name: product.map(|product| product.name.clone()),
+            price: product.map(|product| product.price),
+            literprice: product.map(|product| product.literprice),
+            url: product.map(|product| product.url.clone()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ExportedPreferences {
+    pub notify: bool,
+    pub searches: Vec<SavedSearch>,
+}
+
+pub async fn export(
+    State(state): State<AppState>,
+    auth: Authenticate,
+) -> Result<impl IntoResponse, AppError> {
+    let user = auth.user;
+
+    let mut sessions = state.sessions.get_sessions_for_user(&auth.id).await?;
+    sessions.sort_by_key(|session| session.created_at);
+    let sessions = sessions
+        .into_iter()
+        .map(|session| SessionInfo::from_session(session, &auth.session_id))
+        .collect();
+
+    let mut indices: Vec<i64> = user
+        .favourites
+        .iter()
+        .copied()
+        .chain(user.lists.iter().flat_map(FavouriteList::indices))
+        .collect();
+    indices.sort_unstable();
+    indices.dedup();
+    // A failed lookup would otherwise be exported as products that no longer exist.
+    let products: HashMap<i64, Product> = state
+        .products
+        .get_products_by_indices(&indices)
+        .await?
+        .into_iter()
+        .map(|product| (product.index as i64, product))
+        .collect();
+    let favourites = user
+        .favourites
+        .iter()
+        .map(|&index| ExportedFavourite {
+            index,
+            product: ExportedProduct::from_product(products.get(&index)),
+        })
+        .collect();
+    let lists = user
+        .lists
+        .into_iter()
+        .map(|list| ExportedList {
+            id: list.id,
+            name: list.name,
+            entries: list
+                .entries
+                .into_iter()
+                .map(|entry| ExportedEntry {
+                    product: ExportedProduct::from_product(products.get(&entry.index)),
+                    entry,
+                })
+                .collect(),
+            slug: list.slug,
+        })
+        .collect();
+
+    let searches = state.users.get_saved_searches(&auth.id).await?;
+    let alerts = state.users.get_alerts(&auth.id).await?;
+
+    let bundle = AccountExport {
+        exported: DateTime::now().try_to_rfc3339_string().unwrap_or_default(),
+        user: ExportedUser {
+            id: user.user_id.to_hex(),
+            username: user.username,
+            email: user.email,
+            verified: user.verified,
+        },
+        sessions,
+        favourites,
+        lists,
+        alerts,
+        preferences: ExportedPreferences {
+            notify: user.notify,
+            searches,
+        },
+    };
+
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"snublejuice.json\"",
+        )],
+        Json(bundle),
+    ))
+}
+
+/// A session as shown to its owner, without the secret cookie value.
+#[derive(Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: Option<String>,
+    pub last_seen: Option<String>,
+    pub expires_after: String,
+    pub current: bool,
+}
+
+impl SessionInfo {
+    fn from_session(session: Session, current_session_id: &str) -> Self {
+        SessionInfo {
+            id: session.id.to_hex(),
+            current: session.session_id == current_session_id,
+            user_agent: session.user_agent,
+            ip: session.ip,
+            created_at: session
+                .created_at
+                .and_then(|date| date.try_to_rfc3339_string().ok()),
+            last_seen: session
+                .last_seen
+                .and_then(|date| date.try_to_rfc3339_string().ok()),
+            expires_after: session
+                .expires_after
+                .try_to_rfc3339_string()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn parse_session_id(id: &str) -> Result<ObjectId, AppError> {
+    ObjectId::parse_str(id).map_err(|_| AppError::BadRequest("Ugyldig økt.".to_string()))
+}
+
+pub async fn get_sessions(
+    State(state): State<AppState>,
+    auth: Authenticate,
+) -> Result<Json<Vec<SessionInfo>>, AppError> {
+    let mut sessions = state.sessions.get_sessions_for_user(&auth.id).await?;
+    sessions.sort_by_key(|session| std::cmp::Reverse(session.last_seen));
+
+    Ok(Json(
+        sessions
+            .into_iter()
+            .map(|session| SessionInfo::from_session(session, &auth.session_id))
+            .collect(),
+    ))
+}
+
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Path(id): Path<String>,
+) -> Result<Json<String>, AppError> {
+    let id = parse_session_id(&id)?;
+    state.sessions.delete_session_by_id(&auth.id, &id).await?;
+    Ok(Json("ok".to_string()))
+}
+
+pub async fn revoke_other_sessions(
+    State(state): State<AppState>,
+    auth: Authenticate,
+) -> Result<Json<String>, AppError> {
+    state
+        .sessions
+        .delete_other_sessions(&auth.id, &auth.session_id)
+        .await?;
+    Ok(Json("ok".to_string()))
+}
+
+fn validate_name(name: &str) -> Result<String, AppError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 64 {
+        return Err(AppError::BadRequest(
+            "Navnet må være mellom 1 og 64 tegn.".to_string(),
+        ));
+    }
+    Ok(name.to_string())
+}
+
+fn parse_search_id(search_id: &str) -> Result<ObjectId, AppError> {
+    ObjectId::parse_str(search_id).map_err(|_| AppError::BadRequest("Ugyldig søk.".to_string()))
+}
+
+pub async fn get_searches(
+    State(state): State<AppState>,
+    auth: Authenticate,
+) -> Result<Json<Vec<SavedSearch>>, AppError> {
+    let searches = state.users.get_saved_searches(&auth.id).await?;
+    Ok(Json(searches))
+}
+
+pub async fn create_search(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    subdomain: Subdomain,
+    Json(payload): Json<SavedSearchRequest>,
+) -> Result<Json<SavedSearch>, AppError> {
+    if matches!(subdomain, Subdomain::Landing) {
+        return Err(AppError::BadRequest("Ugyldig subdomene.".to_string()));
+    }
+    if state.users.count_saved_searches(&auth.id).await? >= MAX_SAVED_SEARCHES {
+        return Err(AppError::BadRequest(format!(
+            "Du kan maksimalt lagre {} søk.",
+            MAX_SAVED_SEARCHES
+        )));
+    }
+
+    let mut parameters = payload.parameters;
+    parameters.page = None;
+
+    let mut search = SavedSearch {
+        search_id: ObjectId::new(),
+        user_id: auth.id,
+        name: validate_name(&payload.name)?,
+        subdomain: subdomain.name().to_string(),
+        parameters,
+        matches: vec![],
+        fresh: vec![],
+        checked: DateTime::now(),
+    };
+
+    // Products matching today are the baseline; only later additions are reported as fresh.
+    let prices_updated = state.metadata.get_prices_updated(subdomain.name()).await;
+    search.matches = database::jobs::match_saved_search(&state, &search, prices_updated).await?;
+
+    state.users.create_saved_search(&search).await?;
+    Ok(Json(search))
+}
+
+pub async fn update_search(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Path(search_id): Path<String>,
+    Json(payload): Json<SavedSearchRequest>,
+) -> Result<Json<SavedSearch>, AppError> {
+    let search_id = parse_search_id(&search_id)?;
+    let mut search = state
+        .users
+        .get_saved_searches(&auth.id)
+        .await?
+        .into_iter()
+        .find(|search| search.search_id == search_id)
+        .ok_or(AppError::NotFound)?;
+
+    search.name = validate_name(&payload.name)?;
+    search.parameters = payload.parameters;
+    search.parameters.page = None;
+
+    let prices_updated = state.metadata.get_prices_updated(&search.subdomain).await;
+    search.matches = database::jobs::match_saved_search(&state, &search, prices_updated).await?;
+    search.fresh = vec![];
+    search.checked = DateTime::now();
+
+    state.users.update_saved_search(&search).await?;
+    Ok(Json(search))
+}
+
+pub async fn delete_search(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Path(search_id): Path<String>,
+) -> Result<Json<String>, AppError> {
+    let search_id = parse_search_id(&search_id)?;
+    state
+        .users
+        .delete_saved_search(&auth.id, &search_id)
+        .await?;
+    Ok(Json("ok".to_string()))
+}
+
+fn parse_alert_id(alert_id: &str) -> Result<ObjectId, AppError> {
+    ObjectId::parse_str(alert_id).map_err(|_| AppError::BadRequest("Ugyldig varsel.".to_string()))
+}
+
+pub async fn get_alerts(
+    State(state): State<AppState>,
+    auth: Authenticate,
+) -> Result<Json<Vec<Alert>>, AppError> {
+    let alerts = state.users.get_alerts(&auth.id).await?;
+    Ok(Json(alerts))
+}
+
+pub async fn create_alert(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Json(payload): Json<AlertRequest>,
+) -> Result<Json<Alert>, AppError> {
+    if !payload.threshold.is_finite() || payload.threshold <= 0.0 {
+        return Err(AppError::BadRequest(
+            "Grensen må være større enn null.".to_string(),
+        ));
+    }
+    if state.users.count_alerts(&auth.id).await? >= MAX_ALERTS {
+        return Err(AppError::BadRequest(format!(
+            "Du kan maksimalt ha {} varsler.",
+            MAX_ALERTS
+        )));
+    }
+
+    let product = state
+        .products
+        .get_product(payload.index)
+        .await
+        .ok_or(AppError::NotFound)?;
+    // Today's price is the baseline; the alert fires once a later update crosses the threshold.
+    let value = payload.field.value(&product);
+    if value.is_none() {
+        return Err(AppError::BadRequest(
+            "Produktet har ingen slik pris.".to_string(),
+        ));
+    }
+
+    let alert = Alert {
+        alert_id: ObjectId::new(),
+        user_id: auth.id,
+        index: payload.index,
+        field: payload.field,
+        threshold: payload.threshold,
+        value,
+        triggered: None,
+        pending: false,
+        created: DateTime::now(),
+    };
+    state.users.create_alert(&alert).await?;
+    Ok(Json(alert))
+}
+
+pub async fn dismiss_alerts(
+    State(state): State<AppState>,
+    auth: Authenticate,
+) -> Result<Json<String>, AppError> {
+    state.users.dismiss_alerts(&auth.id).await?;
+    Ok(Json("ok".to_string()))
+}
+
+pub async fn delete_alert(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Path(alert_id): Path<String>,
+) -> Result<Json<String>, AppError> {
+    let alert_id = parse_alert_id(&alert_id)?;
+    state.users.delete_alert(&auth.id, &alert_id).await?;
+    Ok(Json("ok".to_string()))
+}
+
+fn validate_list_name(lists: &[FavouriteList], id: &str, name: &str) -> Result<String, AppError> {
+    let name = validate_name(name)?;
+    if lists
+        .iter()
+        .any(|list| list.id != id && list.name.to_lowercase() == name.to_lowercase())
+    {
+        return Err(AppError::BadRequest(
+            "Du har allerede en liste med dette navnet.".to_string(),
+        ));
+    }
+    Ok(name)
+}
+
+fn validate_entry(payload: ListEntryRequest) -> Result<FavouriteEntry, AppError> {
+    let note = payload
+        .note
+        .map(|note| note.trim().to_string())
+        .filter(|note| !note.is_empty());
+    if note
+        .as_ref()
+        .is_some_and(|note| note.chars().count() > MAX_NOTE_LENGTH)
+    {
+        return Err(AppError::BadRequest(format!(
+            "Notatet kan være maksimalt {} tegn.",
+            MAX_NOTE_LENGTH
+        )));
+    }
+    if payload
+        .target_price
+        .is_some_and(|price| !price.is_finite() || price <= 0.0)
+    {
+        return Err(AppError::BadRequest(
+            "Målprisen må være større enn null.".to_string(),
+        ));
+    }
+    Ok(FavouriteEntry {
+        index: payload.index,
+        note,
+        target_price: payload.target_price,
+        added: DateTime::now(),
+    })
+}
+
+fn find_list<'a>(lists: &'a [FavouriteList], id: &str) -> Result<&'a FavouriteList, AppError> {
+    lists
+        .iter()
+        .find(|list| list.id == id)
+        .ok_or(AppError::NotFound)
+}
+
+fn list_full() -> AppError {
+    AppError::BadRequest(format!(
+        "En liste kan maksimalt ha {} produkter.",
+        MAX_LIST_ENTRIES
+    ))
+}
+
+pub async fn get_lists(auth: Authenticate) -> Json<Vec<FavouriteList>> {
+    Json(auth.user.lists)
+}
+
+pub async fn create_list(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Json(payload): Json<ListRequest>,
+) -> Result<Json<FavouriteList>, AppError> {
+    let list = FavouriteList {
+        id: ObjectId::new().to_hex(),
+        name: validate_list_name(&auth.user.lists, "", &payload.name)?,
+        entries: vec![],
+        slug: None,
+    };
+
+    if !state.users.create_list(&auth.id, &list).await? {
+        return Err(AppError::BadRequest(format!(
+            "Du kan maksimalt ha {} lister.",
+            MAX_FAVOURITE_LISTS
+        )));
+    }
+    Ok(Json(list))
+}
+
+pub async fn rename_list(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Path(list_id): Path<String>,
+    Json(payload): Json<ListRequest>,
+) -> Result<Json<FavouriteList>, AppError> {
+    let name = validate_list_name(&auth.user.lists, &list_id, &payload.name)?;
+
+    let list = state
+        .users
+        .rename_list(&auth.id, &list_id, &name)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    Ok(Json(list))
+}
+
+pub async fn delete_list(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Path(list_id): Path<String>,
+) -> Result<Json<String>, AppError> {
+    if !state.users.delete_list(&auth.id, &list_id).await? {
+        return Err(AppError::NotFound);
+    }
+    Ok(Json("ok".to_string()))
+}
+
+/// Publishes a list at `/liste/{slug}`. Sharing an already shared list keeps its address.
+pub async fn share_list(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Path(list_id): Path<String>,
+) -> Result<Json<FavouriteList>, AppError> {
+    // The slug is public, so the hash half of the token is not needed.
+    let slug = new_token().0;
+
+    let list = state
+        .users
+        .share_list(&auth.id, &list_id, &slug)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    Ok(Json(list))
+}
+
+/// Revokes the public address of a list. Sharing it again creates a new address.
+pub async fn unshare_list(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Path(list_id): Path<String>,
+) -> Result<Json<String>, AppError> {
+    if !state.users.unshare_list(&auth.id, &list_id).await? {
+        return Err(AppError::NotFound);
+    }
+    Ok(Json("ok".to_string()))
+}
+
+/// Adds a product to a list, or updates its note and target price if it is already there.
+pub async fn put_entry(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Path(list_id): Path<String>,
+    Json(payload): Json<ListEntryRequest>,
+) -> Result<Json<FavouriteList>, AppError> {
+    let entry = validate_entry(payload)?;
+    find_list(&auth.user.lists, &list_id)?;
+
+    let products = state
+        .products
+        .get_products_by_indices(&[entry.index])
+        .await?;
+    if products.is_empty() {
+        return Err(AppError::BadRequest("Produktet finnes ikke.".to_string()));
+    }
+
+    // The list was found above, so it can only be missing here if it was full.
+    let list = state
+        .users
+        .put_entry(&auth.id, &list_id, &entry)
+        .await?
+        .ok_or_else(list_full)?;
+    Ok(Json(list))
+}
+
+pub async fn delete_entry(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Path((list_id, index)): Path<(String, i64)>,
+) -> Result<Json<String>, AppError> {
+    if !state.users.remove_entry(&auth.id, &list_id, index).await? {
+        return Err(AppError::NotFound);
+    }
     Ok(Json("ok".to_string()))
 }
+
+/// Moves an entry, along with its note and target price, to another list.
+pub async fn move_entry(
+    State(state): State<AppState>,
+    auth: Authenticate,
+    Path(list_id): Path<String>,
+    Json(payload): Json<MoveEntryRequest>,
+) -> Result<Json<String>, AppError> {
+    let lists = &auth.user.lists;
+    let target = find_list(lists, &payload.to)?;
+    let entry = find_list(lists, &list_id)?
+        .entry(payload.index)
+        .ok_or(AppError::NotFound)?;
+    if payload.to == list_id {
+        return Ok(Json("ok".to_string()));
+    }
+
+    if !state
+        .users
+        .move_entry(&auth.id, &list_id, &payload.to, entry)
+        .await?
+    {
+        return Err(if target.entries.len() >= MAX_LIST_ENTRIES {
+            list_full()
+        } else {
+            AppError::NotFound
+        });
+    }
+    Ok(Json("ok".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_name_trims_and_bounds_length() {
+        assert_eq!(validate_name("  Barolo ").unwrap(), "Barolo");
+        assert!(matches!(validate_name("   "), Err(AppError::BadRequest(_))));
+        assert!(matches!(
+            validate_name(&"x".repeat(65)),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn validate_username_trims_and_bounds_length() {
+        assert_eq!(validate_username(" snubler ").unwrap(), "snubler");
+        assert!(validate_username("æ").is_err());
+        assert!(validate_username(&"ø".repeat(32)).is_ok());
+        assert!(validate_username(&"ø".repeat(33)).is_err());
+    }
+
+    #[test]
+    fn validate_entry_trims_note_and_rejects_invalid_prices() {
+        let request = |note: &str, target_price: Option<f64>| ListEntryRequest {
+            index: 1,
+            note: Some(note.to_string()),
+            target_price,
+        };
+        let entry = validate_entry(request("  til julebordet ", Some(299.0))).unwrap();
+        assert_eq!(entry.note.as_deref(), Some("til julebordet"));
+        assert_eq!(entry.target_price, Some(299.0));
+        assert!(validate_entry(request("   ", None)).unwrap().note.is_none());
+        assert!(validate_entry(request(&"x".repeat(MAX_NOTE_LENGTH + 1), None)).is_err());
+        assert!(validate_entry(request("", Some(0.0))).is_err());
+        assert!(validate_entry(request("", Some(f64::NAN))).is_err());
+    }
+
+    #[test]
+    fn validate_list_name_is_unique_per_user_ignoring_case() {
+        let lists = vec![FavouriteList {
+            id: "a".to_string(),
+            name: "Julebord".to_string(),
+            entries: vec![],
+            slug: None,
+        }];
+        assert!(validate_list_name(&lists, "", "julebord").is_err());
+        assert_eq!(
+            validate_list_name(&lists, "a", "JULEBORD").unwrap(),
+            "JULEBORD"
+        );
+        assert_eq!(
+            validate_list_name(&lists, "", "Kjeller").unwrap(),
+            "Kjeller"
+        );
+    }
+
+    #[test]
+    fn parse_search_id_rejects_invalid_ids() {
+        let id = ObjectId::new();
+        assert_eq!(parse_search_id(&id.to_hex()).unwrap(), id);
+        assert!(matches!(
+            parse_search_id("abc"),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+}
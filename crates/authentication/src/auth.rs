@@ -1,57 +1,102 @@
-use axum::{Json, extract::State};
+use axum::{
+    Json,
+    extract::{Query, State},
+    response::Redirect,
+};
 use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
-use mongodb::bson::DateTime;
+use mongodb::bson::{DateTime, oid::ObjectId};
+use regex::Regex;
+use std::sync::LazyLock;
 use std::time::{Duration, SystemTime};
 use time::Duration as TimeDuration;
 use uuid::Uuid;
 
-use crate::middle;
-use database::users;
+use crate::middle::{self, Authenticate, ClientInfo};
 use shared::{
     errors::AppError,
-    models::{ONE_MONTH, Session, User},
-    query::{LoginRequest, SignupRequest},
+    mail::Mail,
+    models::{
+        ONE_MONTH, RESET_TOKEN_LIFETIME, Session, Token, TokenKind, User, VERIFY_TOKEN_LIFETIME,
+    },
+    query::{ForgotRequest, LoginRequest, ResetRequest, SignupRequest, VerifyRequest},
     state::AppState,
 };
 
-pub async fn login(
-    State(state): State<AppState>,
-    jar: CookieJar,
-    Json(payload): Json<LoginRequest>,
-) -> Result<(CookieJar, Json<&'static str>), AppError> {
-    let user: User = match users::get_user_by_name(&state.db, &payload.username).await {
-        Some(user) => user,
-        None => return Err(AppError::NotFound),
-    };
+static RE_EMAIL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[^\s@]+@[^\s@]+\.[^\s@.]+$").unwrap());
 
-    if !middle::verify_password(&payload.password, &user.password) {
-        return Err(AppError::Unauthorized);
+/// The trimmed address, if it looks like one.
+pub fn validate_email(email: &str) -> Result<String, AppError> {
+    let email = email.trim();
+    if email.len() > 254 || !RE_EMAIL.is_match(email) {
+        return Err(AppError::BadRequest("Ugyldig e-postadresse.".to_string()));
     }
+    Ok(email.to_string())
+}
 
-    users::delete_sessions_for_user(&state.db, &user.user_id).await?;
+/// Fails if another user already has the address, regardless of case.
+pub async fn ensure_email_available(state: &AppState, email: &str) -> Result<(), AppError> {
+    if state.users.get_user_by_email(email).await.is_some() {
+        return Err(AppError::BadRequest(
+            "E-postadressen er allerede i bruk.".to_string(),
+        ));
+    }
+    Ok(())
+}
 
+/// Stores a new session for the user, alongside any it already has on other devices, and returns
+/// the cookie carrying it.
+async fn start_session(
+    state: &AppState,
+    user_id: ObjectId,
+    client: ClientInfo,
+) -> Result<Cookie<'static>, AppError> {
     let session_id = Uuid::new_v4().to_string();
 
     let expires_after =
         DateTime::from_system_time(SystemTime::now() + Duration::from_secs(ONE_MONTH));
     let session = Session {
-        user_id: user.user_id,
+        id: ObjectId::new(),
+        user_id,
         session_id: session_id.clone(),
         expires_after,
+        user_agent: client.user_agent,
+        ip: client.ip,
+        created_at: Some(DateTime::now()),
+        last_seen: Some(DateTime::now()),
     };
-    users::store_session(&state.db, session).await?;
-
-    let domain =
-        std::env::var("COOKIE_DOMAIN").unwrap_or_else(|_| "snublejuice.localhost".to_string());
+    state.sessions.store_session(session).await?;
 
-    let cookie = Cookie::build(("session_id", session_id))
+    Ok(Cookie::build(("session_id", session_id))
         .path("/")
-        .domain(domain)
+        .domain(state.config.cookie_domain.clone())
         .http_only(true)
         .secure(true)
         .same_site(SameSite::Lax)
         .max_age(TimeDuration::seconds(ONE_MONTH as i64))
-        .build();
+        .build())
+}
+
+pub async fn login(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    client: ClientInfo,
+    Json(payload): Json<LoginRequest>,
+) -> Result<(CookieJar, Json<&'static str>), AppError> {
+    let user: User = match state.users.get_user_by_name(&payload.username).await {
+        Some(user) => user,
+        None => {
+            metrics::counter!("login_failures_total", "reason" => "unknown_user").increment(1);
+            return Err(AppError::NotFound);
+        }
+    };
+
+    if !middle::verify_password(&payload.password, &user.password) {
+        metrics::counter!("login_failures_total", "reason" => "wrong_password").increment(1);
+        return Err(AppError::Unauthorized);
+    }
+
+    let cookie = start_session(&state, user.user_id, client).await?;
 
     Ok((jar.add(cookie), Json("ok")))
 }
@@ -59,9 +104,12 @@ pub async fn login(
 pub async fn signup(
     State(state): State<AppState>,
     jar: CookieJar,
+    client: ClientInfo,
     Json(payload): Json<SignupRequest>,
 ) -> Result<(CookieJar, Json<&'static str>), AppError> {
-    if users::get_user_by_name(&state.db, &payload.username)
+    if state
+        .users
+        .get_user_by_name(&payload.username)
         .await
         .is_some()
     {
@@ -70,43 +118,237 @@ pub async fn signup(
         ));
     }
 
+    let email = validate_email(&payload.email)?;
+    ensure_email_available(&state, &email).await?;
+    middle::validate_password(&payload.password)?;
+
     let hashed_password =
         middle::hash_password(&payload.password).map_err(|_| AppError::InternalServerError)?;
 
-    let user_id = mongodb::bson::oid::ObjectId::new();
+    let user_id = ObjectId::new();
 
     let new_user = User {
         user_id,
         username: payload.username.clone(),
         password: hashed_password,
-        email: payload.email.clone(),
+        email,
         favourites: vec![],
         notify: payload.notify,
+        verified: false,
+        lists: vec![],
     };
 
-    users::create_user(&state.db, &new_user).await?;
+    state.users.create_user(&new_user).await?;
 
-    let session_id = Uuid::new_v4().to_string();
+    // The account is usable without a verified address, so a failing mail server must not
+    // fail the signup. The link can be requested again from the profile.
+    if let Err(error) = send_verification(&state, &new_user).await {
+        tracing::error!(%error, user_id = %new_user.user_id, "Could not send verification");
+    }
+
+    let cookie = start_session(&state, user_id, client).await?;
+
+    Ok((jar.add(cookie), Json("ok")))
+}
+
+pub async fn forgot(
+    State(state): State<AppState>,
+    Json(payload): Json<ForgotRequest>,
+) -> Json<&'static str> {
+    // Respond the same, and as quickly, whether or not the address belongs to a user, so the
+    // lookup and mail happen after responding and their errors are only logged.
+    tokio::spawn(async move {
+        if let Err(error) = send_reset(&state, payload.email.trim()).await {
+            tracing::error!(%error, "Could not send password reset");
+        }
+    });
+    Json("ok")
+}
+
+async fn send_reset(state: &AppState, email: &str) -> Result<(), AppError> {
+    let Some(user) = state.users.get_user_by_email(email).await else {
+        return Ok(());
+    };
+
+    state
+        .tokens
+        .delete_tokens_for_user(&user.user_id, TokenKind::Reset)
+        .await?;
+
+    let (token, hash) = middle::new_token();
     let expires_after =
-        DateTime::from_system_time(SystemTime::now() + Duration::from_secs(ONE_MONTH));
-    let session = Session {
-        user_id,
-        session_id: session_id.clone(),
-        expires_after,
+        DateTime::from_system_time(SystemTime::now() + Duration::from_secs(RESET_TOKEN_LIFETIME));
+    state
+        .tokens
+        .store_token(Token {
+            user_id: user.user_id,
+            kind: TokenKind::Reset,
+            hash,
+            expires_after,
+        })
+        .await?;
+
+    let url = format!(
+        "{}/?reset={}",
+        state.config.public_url.trim_end_matches('/'),
+        token
+    );
+    let mail = Mail {
+        to: user.email,
+        subject: "Tilbakestill passordet ditt".to_string(),
+        body: format!(
+            "<p>Noen har bedt om å tilbakestille passordet ditt på Snublejuice.</p>\n\
+             <p><a href=\"{url}\">Velg et nytt passord</a></p>\n\
+             <p>Lenken kan brukes én gang og er gyldig i én time. \
+             Har du ikke bedt om dette kan du se bort fra e-posten.</p>"
+        ),
     };
-    users::store_session(&state.db, session).await?;
+    state.mailer.send(&mail).await
+}
 
-    let domain =
-        std::env::var("COOKIE_DOMAIN").unwrap_or_else(|_| "snublejuice.localhost".to_string());
+pub async fn reset(
+    State(state): State<AppState>,
+    Json(payload): Json<ResetRequest>,
+) -> Result<Json<&'static str>, AppError> {
+    middle::validate_password(&payload.password)?;
 
-    let cookie = Cookie::build(("session_id", session_id))
-        .path("/")
-        .domain(domain)
-        .http_only(true)
-        .secure(true)
-        .same_site(SameSite::Lax)
-        .max_age(TimeDuration::seconds(ONE_MONTH as i64))
-        .build();
+    let token = state
+        .tokens
+        .consume_token(TokenKind::Reset, &middle::hash_token(&payload.token))
+        .await?
+        .ok_or_else(|| AppError::BadRequest("Lenken er ugyldig eller utløpt.".to_string()))?;
 
-    Ok((jar.add(cookie), Json("ok")))
+    let hashed_password =
+        middle::hash_password(&payload.password).map_err(|_| AppError::InternalServerError)?;
+    state
+        .users
+        .update_password(&token.user_id, &hashed_password)
+        .await?;
+
+    // Whoever had access to the account should not keep it.
+    state
+        .sessions
+        .delete_sessions_for_user(&token.user_id)
+        .await?;
+
+    Ok(Json("ok"))
+}
+
+/// E-mails the user a link to confirm their address, replacing any earlier link.
+pub async fn send_verification(state: &AppState, user: &User) -> Result<(), AppError> {
+    state
+        .tokens
+        .delete_tokens_for_user(&user.user_id, TokenKind::Verify)
+        .await?;
+
+    let (token, hash) = middle::new_token();
+    let expires_after =
+        DateTime::from_system_time(SystemTime::now() + Duration::from_secs(VERIFY_TOKEN_LIFETIME));
+    state
+        .tokens
+        .store_token(Token {
+            user_id: user.user_id,
+            kind: TokenKind::Verify,
+            hash,
+            expires_after,
+        })
+        .await?;
+
+    let url = format!(
+        "{}/account/verify?token={}",
+        state.config.public_url.trim_end_matches('/'),
+        token
+    );
+    let mail = Mail {
+        to: user.email.clone(),
+        subject: "Bekreft e-postadressen din".to_string(),
+        body: format!(
+            "<p>Bekreft at denne adressen tilhører deg for å få varsler fra Snublejuice.</p>\n\
+             <p><a href=\"{url}\">Bekreft e-postadressen</a></p>\n\
+             <p>Lenken er gyldig i en uke. \
+             Har du ikke registrert deg kan du se bort fra e-posten.</p>"
+        ),
+    };
+    state.mailer.send(&mail).await
+}
+
+/// Sends a verification link to each user that wants notifications but signed up before addresses
+/// were verified. Their digests stop until they confirm, as nothing says the address is theirs.
+/// Users are claimed as they are asked, so a restart does not ask anyone twice, and a link lost to
+/// a failing mail server can be requested again from the profile.
+pub async fn ask_existing_users_to_verify(state: AppState) {
+    loop {
+        let user = match state.users.take_user_to_verify().await {
+            Ok(Some(user)) => user,
+            Ok(None) => return,
+            Err(error) => {
+                tracing::error!(%error, "Could not fetch users to verify");
+                return;
+            }
+        };
+        if let Err(error) = send_verification(&state, &user).await {
+            tracing::error!(%error, user_id = %user.user_id, "Could not send verification");
+        }
+    }
+}
+
+pub async fn verify(
+    State(state): State<AppState>,
+    Query(payload): Query<VerifyRequest>,
+) -> Result<Redirect, AppError> {
+    let token = state
+        .tokens
+        .consume_token(TokenKind::Verify, &middle::hash_token(&payload.token))
+        .await?;
+
+    match token {
+        Some(token) => {
+            state.users.set_verified(&token.user_id).await?;
+            Ok(Redirect::to("/?verified=true"))
+        }
+        None => Ok(Redirect::to("/?verified=false")),
+    }
+}
+
+pub async fn resend_verification(
+    State(state): State<AppState>,
+    auth: Authenticate,
+) -> Result<Json<&'static str>, AppError> {
+    if auth.user.verified {
+        return Err(AppError::BadRequest(
+            "E-postadressen er allerede bekreftet.".to_string(),
+        ));
+    }
+    send_verification(&state, &auth.user).await?;
+    Ok(Json("ok"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_email_accepts_addresses_and_trims() {
+        assert_eq!(
+            validate_email("  ola.nordmann+vin@example.no ").unwrap(),
+            "ola.nordmann+vin@example.no"
+        );
+        assert!(validate_email("Kari@Sub.Example.COM").is_ok());
+    }
+
+    #[test]
+    fn validate_email_rejects_malformed_addresses() {
+        for email in [
+            "",
+            "ola",
+            "ola@",
+            "@example.com",
+            "ola@example",
+            "ola @example.com",
+            "ola@example.",
+        ] {
+            assert!(validate_email(email).is_err(), "{email}");
+        }
+        assert!(validate_email(&format!("{}@example.com", "a".repeat(250))).is_err());
+    }
 }
@@ -3,12 +3,28 @@ pub mod middle;
 
 use std::sync::Arc;
 
-use axum::{Router, routing::post};
+use axum::{
+    Router,
+    body::Body,
+    http::Response,
+    routing::{get, post},
+};
 use shared::state::AppState;
 use tower_governor::{
-    GovernorLayer, governor::GovernorConfigBuilder, key_extractor::SmartIpKeyExtractor,
+    GovernorError, GovernorLayer, governor::GovernorConfigBuilder,
+    key_extractor::SmartIpKeyExtractor,
 };
 
+/// Counts the requests turned away by the `limiter`, then answers as the governor would.
+fn rejected(limiter: &'static str) -> impl Fn(GovernorError) -> Response<Body> + Send + Sync {
+    move |error| {
+        if let GovernorError::TooManyRequests { .. } = error {
+            metrics::counter!("rate_limit_rejections_total", "limiter" => limiter).increment(1);
+        }
+        error.into()
+    }
+}
+
 pub fn router() -> Router<AppState> {
     let governor_conf = Arc::new(
         GovernorConfigBuilder::default()
@@ -19,8 +35,28 @@ pub fn router() -> Router<AppState> {
             .unwrap(),
     );
 
+    // Each request may send an e-mail, so recovery is limited harder than logging in.
+    let recovery_conf = Arc::new(
+        GovernorConfigBuilder::default()
+            .per_second(60)
+            .burst_size(5)
+            .key_extractor(SmartIpKeyExtractor)
+            .finish()
+            .unwrap(),
+    );
+
+    let recovery = Router::new()
+        .route("/account/forgot", post(auth::forgot))
+        .route("/account/reset", post(auth::reset))
+        .route(
+            "/account/verify",
+            get(auth::verify).post(auth::resend_verification),
+        )
+        .layer(GovernorLayer::new(recovery_conf).error_handler(rejected("recovery")));
+
     Router::new()
         .route("/account/login", post(auth::login))
         .route("/account/signup", post(auth::signup))
-        .layer(GovernorLayer::new(governor_conf))
+        .layer(GovernorLayer::new(governor_conf).error_handler(rejected("login")))
+        .merge(recovery)
 }
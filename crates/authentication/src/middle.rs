@@ -1,14 +1,20 @@
 use axum::{
-    extract::{FromRef, FromRequestParts},
-    http::request::Parts,
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::{header, request::Parts},
 };
 use axum_extra::extract::cookie::CookieJar;
 use bcrypt::{DEFAULT_COST, hash, verify};
-use mongodb::{Database, bson::oid::ObjectId};
+use mongodb::bson::{DateTime, oid::ObjectId};
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
 use tokio::spawn;
+use uuid::Uuid;
 
-use database::users;
-use shared::{errors::AppError, models::User};
+use shared::{
+    errors::AppError,
+    models::{ONE_MONTH, User},
+    state::AppState,
+};
 
 pub struct Authenticate {
     pub id: ObjectId,
@@ -18,13 +24,13 @@ pub struct Authenticate {
 
 impl<S> FromRequestParts<S> for Authenticate
 where
-    Database: FromRef<S>,
+    AppState: FromRef<S>,
     S: Send + Sync,
 {
     type Rejection = AppError;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let db = Database::from_ref(state);
+        let app = AppState::from_ref(state);
 
         let jar = CookieJar::from_request_parts(parts, state)
             .await
@@ -37,20 +43,37 @@ where
             .ok_or(AppError::Unauthorized)?;
 
         // Check the session cookie validity.
-        let session = users::get_user_by_session_id(&db, &session_id)
+        let session = app
+            .sessions
+            .get_session(&session_id)
             .await
             .map_err(|_| AppError::Unauthorized)?;
 
-        let user = users::get_user_by_id(&db, &session.user_id)
+        // Stores may keep expired sessions around until they are swept.
+        if session.expires_after <= DateTime::now() {
+            let sessions = app.sessions.clone();
+            spawn(async move {
+                let _ = sessions.delete_session(&session.session_id).await;
+            });
+            return Err(AppError::Unauthorized);
+        }
+
+        let user = app
+            .users
+            .get_user_by_id(&session.user_id)
             .await
             .ok_or(AppError::Unauthorized)?;
 
-        // Slide the expiration date forward.
-        let db_clone = db.clone();
-        let session_id_clone = session_id.clone();
-        spawn(async move {
-            let _ = users::update_expiration(db_clone, session_id_clone).await;
-        });
+        // Slide the expiration date forward, once enough of the lifetime has passed.
+        if needs_refresh(
+            session.expires_after,
+            DateTime::now(),
+            app.config.session_refresh_fraction,
+        ) {
+            app.refreshes.push(session_id.clone());
+        }
+
+        tracing::Span::current().record("user_id", tracing::field::display(&session.user_id));
 
         Ok(Authenticate {
             id: session.user_id,
@@ -60,6 +83,13 @@ where
     }
 }
 
+/// Whether at least `fraction` of the session lifetime has passed since its expiry was last set.
+pub fn needs_refresh(expires_after: DateTime, now: DateTime, fraction: f64) -> bool {
+    let lifetime = (ONE_MONTH * 1000) as f64;
+    let remaining = (expires_after.timestamp_millis() - now.timestamp_millis()) as f64;
+    lifetime - remaining >= fraction * lifetime
+}
+
 pub fn verify_password(password: &str, hashed: &str) -> bool {
     verify(password, hashed).unwrap_or(false)
 }
@@ -68,11 +98,73 @@ pub fn hash_password(password: &str) -> Result<String, bcrypt::BcryptError> {
     hash(password, DEFAULT_COST)
 }
 
+pub fn validate_password(password: &str) -> Result<(), AppError> {
+    if password.chars().count() < 4 {
+        return Err(AppError::BadRequest(
+            "Passordet må være minst 4 tegn.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Tokens are random enough that a fast hash suffices, and it lets them be looked up by hash.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// A fresh token to send to the user, and the hash to store.
+pub fn new_token() -> (String, String) {
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let hash = hash_token(&token);
+    (token, hash)
+}
+
+/// The device a request comes from, as recorded on new sessions.
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let get = |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+
+        let user_agent =
+            get(header::USER_AGENT.as_str()).map(|agent| agent.chars().take(256).collect());
+
+        // Behind the proxy the peer is the proxy itself, so prefer the forwarded client address.
+        let ip = get("x-forwarded-for")
+            .and_then(|forwarded| forwarded.split(',').next())
+            .map(|ip| ip.trim().to_string())
+            .or_else(|| get("x-real-ip").map(str::to_string))
+            .or_else(|| {
+                parts
+                    .extensions
+                    .get::<ConnectInfo<SocketAddr>>()
+                    .map(|ConnectInfo(addr)| addr.ip().to_string())
+            });
+
+        Ok(ClientInfo { user_agent, ip })
+    }
+}
+
 pub struct MaybeAuthenticate(pub Option<User>);
 
 impl<S> FromRequestParts<S> for MaybeAuthenticate
 where
-    Database: FromRef<S>,
+    AppState: FromRef<S>,
     S: Send + Sync,
 {
     type Rejection = std::convert::Infallible;
@@ -100,4 +192,27 @@ mod tests {
     fn verify_password_rejects_invalid_hash() {
         assert!(!verify_password("password", "not-a-bcrypt-hash"));
     }
+
+    #[test]
+    fn needs_refresh_after_fraction_of_lifetime() {
+        let now = DateTime::now();
+        let in_days =
+            |days: i64| DateTime::from_millis(now.timestamp_millis() + days * 24 * 60 * 60 * 1000);
+
+        assert!(!needs_refresh(in_days(30), now, 0.1));
+        assert!(!needs_refresh(in_days(28), now, 0.1));
+        assert!(needs_refresh(in_days(26), now, 0.1));
+        assert!(needs_refresh(in_days(30), now, 0.0));
+        assert!(!needs_refresh(in_days(1), now, 1.0));
+    }
+
+    #[test]
+    fn new_token_is_unique_and_hashed() {
+        let (token, hash) = new_token();
+        let (other, _) = new_token();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, other);
+        assert_eq!(hash, hash_token(&token));
+        assert_ne!(hash, token);
+    }
 }
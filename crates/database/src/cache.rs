@@ -0,0 +1,219 @@
+use mongodb::bson::{Bson, Document};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use shared::models::{Page, Product};
+
+/// How long listings, pages, indices and distinct values are kept. The catalogue only changes with
+/// the monthly update, which clears the cache as soon as it is noticed.
+pub const QUERY_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// How long the price status of a subdomain is trusted, i.e. how long it may take before the
+/// monthly update is noticed.
+pub const PRICES_TTL: Duration = Duration::from_secs(30);
+
+/// Entries per map. Free-text searches make for many distinct keys, so the map is emptied rather
+/// than allowed to grow without bound.
+const MAX_ENTRIES: usize = 1024;
+
+struct Entry<V> {
+    value: V,
+    expires: Instant,
+}
+
+/// A map whose entries are dropped once `ttl` has passed since they were inserted.
+pub struct TtlMap<V> {
+    ttl: Duration,
+    entries: RwLock<HashMap<String, Entry<V>>>,
+}
+
+impl<V: Clone> TtlMap<V> {
+    pub fn new(ttl: Duration) -> Self {
+        TtlMap {
+            ttl,
+            entries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<V> {
+        let entries = self.entries.read().unwrap();
+        entries
+            .get(key)
+            .filter(|entry| entry.expires > Instant::now())
+            .map(|entry| entry.value.clone())
+    }
+
+    pub fn insert(&self, key: String, value: V) {
+        let now = Instant::now();
+        let mut entries = self.entries.write().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, entry| entry.expires > now);
+            if entries.len() >= MAX_ENTRIES {
+                entries.clear();
+            }
+        }
+        entries.insert(
+            key,
+            Entry {
+                value,
+                expires: now + self.ttl,
+            },
+        );
+    }
+
+    pub fn clear(&self) {
+        self.entries.write().unwrap().clear();
+    }
+}
+
+/// Results of the catalogue queries, shared by every request handled by the process.
+pub struct QueryCache {
+    pub products: TtlMap<Vec<Product>>,
+    pub pages: TtlMap<Page<Product>>,
+    pub indices: TtlMap<Vec<i64>>,
+    pub distinct: TtlMap<Vec<String>>,
+    /// The names searched when Atlas Search is not used, under the key `names`.
+    pub names: TtlMap<Arc<Vec<(i64, String)>>>,
+    prices: TtlMap<bool>,
+    /// The last price status read from the database, per subdomain.
+    observed: Mutex<HashMap<String, bool>>,
+}
+
+impl Default for QueryCache {
+    fn default() -> Self {
+        QueryCache::new(QUERY_TTL, PRICES_TTL)
+    }
+}
+
+impl QueryCache {
+    pub fn new(query_ttl: Duration, prices_ttl: Duration) -> Self {
+        QueryCache {
+            products: TtlMap::new(query_ttl),
+            pages: TtlMap::new(query_ttl),
+            indices: TtlMap::new(query_ttl),
+            distinct: TtlMap::new(query_ttl),
+            names: TtlMap::new(query_ttl),
+            prices: TtlMap::new(prices_ttl),
+            observed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Drops every cached query result. The price status is kept, as it is what tells when to
+    /// invalidate.
+    pub fn invalidate(&self) {
+        self.products.clear();
+        self.pages.clear();
+        self.indices.clear();
+        self.distinct.clear();
+        self.names.clear();
+    }
+
+    pub fn prices(&self, subdomain: &str) -> Option<bool> {
+        self.prices.get(subdomain)
+    }
+
+    /// Records a fresh read of `metadata.stock.prices`, and invalidates the cache if the status of
+    /// the subdomain changed since it was last read.
+    pub fn observe_prices(&self, subdomain: &str, updated: bool) {
+        self.prices.insert(subdomain.to_string(), updated);
+        let previous = self
+            .observed
+            .lock()
+            .unwrap()
+            .insert(subdomain.to_string(), updated);
+        if previous.is_some_and(|previous| previous != updated) {
+            self.invalidate();
+        }
+    }
+}
+
+/// The cache key of a pipeline or filter. Keys of documents are sorted, so that filters built in a
+/// different order share an entry, except within `$sort` where the order is significant.
+pub fn key(documents: &[Document]) -> String {
+    let normalized = documents
+        .iter()
+        .map(|document| Bson::Document(normalize(document)))
+        .collect();
+    Bson::Array(normalized).into_relaxed_extjson().to_string()
+}
+
+fn normalize(document: &Document) -> Document {
+    let mut entries: Vec<(&String, &Bson)> = document.iter().collect();
+    entries.sort_by_key(|(key, _)| *key);
+    entries
+        .into_iter()
+        .map(|(key, value)| {
+            let value = if key == "$sort" {
+                value.clone()
+            } else {
+                normalize_value(value)
+            };
+            (key.clone(), value)
+        })
+        .collect()
+}
+
+fn normalize_value(value: &Bson) -> Bson {
+    match value {
+        Bson::Document(document) => Bson::Document(normalize(document)),
+        Bson::Array(values) => Bson::Array(values.iter().map(normalize_value).collect()),
+        _ => value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    #[test]
+    fn key_ignores_field_order_except_in_sort() {
+        let a = key(&[
+            doc! { "$match": { "country": "Italia", "price": { "$gt": 100, "$lt": 200 } } },
+            doc! { "$sort": { "discount": 1, "index": 1 } },
+        ]);
+        let b = key(&[
+            doc! { "$match": { "price": { "$lt": 200, "$gt": 100 }, "country": "Italia" } },
+            doc! { "$sort": { "discount": 1, "index": 1 } },
+        ]);
+        let c = key(&[
+            doc! { "$match": { "country": "Italia", "price": { "$gt": 100, "$lt": 200 } } },
+            doc! { "$sort": { "index": 1, "discount": 1 } },
+        ]);
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_ne!(key(&[doc! { "$limit": 1 }]), key(&[doc! { "$limit": 2 }]));
+    }
+
+    #[test]
+    fn entries_expire_after_ttl() {
+        let map = TtlMap::new(Duration::ZERO);
+        map.insert("count".to_string(), 3u64);
+        assert_eq!(map.get("count"), None);
+
+        let map = TtlMap::new(QUERY_TTL);
+        map.insert("count".to_string(), 3u64);
+        assert_eq!(map.get("count"), Some(3));
+        map.clear();
+        assert_eq!(map.get("count"), None);
+    }
+
+    #[test]
+    fn changed_price_status_invalidates_queries() {
+        let cache = QueryCache::default();
+        cache.indices.insert("all".to_string(), vec![10]);
+
+        cache.observe_prices("vinmonopolet", false);
+        cache.observe_prices("vinmonopolet", false);
+        cache.observe_prices("taxfree", true);
+        assert_eq!(cache.indices.get("all"), Some(vec![10]));
+        assert_eq!(cache.prices("vinmonopolet"), Some(false));
+
+        cache.observe_prices("vinmonopolet", true);
+        assert_eq!(cache.indices.get("all"), None);
+        assert_eq!(cache.prices("vinmonopolet"), Some(true));
+    }
+}
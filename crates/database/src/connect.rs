@@ -2,23 +2,18 @@ use mongodb::{
     Client, Database,
     options::{ClientOptions, ServerApi, ServerApiVersion},
 };
-use std::env;
 
-pub async fn get_database(key: &str, db: &str) -> Result<Database, String> {
-    match env::var(key) {
-        Ok(uri) => {
-            let mut options = ClientOptions::parse(uri)
-                .await
-                .expect("Could not parse the database uri.");
+pub async fn get_database(uri: &str, db: &str) -> Result<Database, String> {
+    let mut options = ClientOptions::parse(uri)
+        .await
+        .map_err(|error| format!("Could not parse the database uri: {}", error))?;
 
-            let server_api = ServerApi::builder().version(ServerApiVersion::V1).build();
-            options.server_api = Some(server_api);
+    let server_api = ServerApi::builder().version(ServerApiVersion::V1).build();
+    options.server_api = Some(server_api);
 
-            let client = Client::with_options(options).expect("Unable to create the client.");
-            let database = client.database(db);
+    let client = Client::with_options(options)
+        .map_err(|error| format!("Unable to create the client: {}", error))?;
+    let database = client.database(db);
 
-            Ok(database)
-        }
-        Err(error) => Err(format!("No database URI found: {:?}", error).to_string()),
-    }
+    Ok(database)
 }
@@ -0,0 +1,53 @@
+use mongodb::{
+    Database, IndexModel,
+    bson::{Document, doc},
+    options::{Collation, CollationStrength, IndexOptions},
+};
+use std::time::Duration;
+
+/// Compares e-mail addresses regardless of case. Queries on `email` must use it too, or they
+/// cannot use its index.
+pub fn email_collation() -> Collation {
+    Collation::builder()
+        .locale("nb")
+        .strength(CollationStrength::Secondary)
+        .build()
+}
+
+/// Creates the indexes the application relies on. Documents with an `expiresAfter` date are
+/// removed by MongoDB's TTL monitor once that date has passed.
+pub async fn ensure_indexes(db: &Database) {
+    let ttl = || IndexOptions::builder().expire_after(Duration::ZERO).build();
+    let unique = || IndexOptions::builder().unique(true).build();
+    let unique_email = IndexOptions::builder()
+        .unique(true)
+        .collation(email_collation())
+        .build();
+
+    let indexes: [(&str, Document, Option<IndexOptions>); 9] = [
+        ("alerts", doc! { "user_id": 1 }, None),
+        ("alerts", doc! { "field": 1 }, None),
+        ("users", doc! { "lists.slug": 1 }, None),
+        ("users", doc! { "email": 1 }, Some(unique_email)),
+        ("sessions", doc! { "expiresAfter": 1 }, Some(ttl())),
+        ("sessions", doc! { "session_id": 1 }, Some(unique())),
+        ("sessions", doc! { "user_id": 1 }, None),
+        ("tokens", doc! { "expiresAfter": 1 }, Some(ttl())),
+        ("tokens", doc! { "hash": 1 }, None),
+    ];
+
+    for (collection, keys, options) in indexes {
+        let index = IndexModel::builder()
+            .keys(keys.clone())
+            .options(options)
+            .build();
+        if let Err(error) = db
+            .collection::<Document>(collection)
+            .create_index(index)
+            .await
+        {
+            // An existing index with other options is left in place rather than failing startup.
+            tracing::warn!(%error, ?keys, collection, "Could not create index");
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use mongodb::bson::{Document, doc};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use shared::{
+    errors::AppError,
+    models::{AlertField, MAX_SEARCH_MATCHES, Product, SavedSearch},
+    state::AppState,
+    subdomain::Subdomain,
+};
+
+pub const POLL_INTERVAL: Duration = Duration::from_secs(15 * 60);
+pub const SUBDOMAINS: [&str; 2] = ["vinmonopolet", "taxfree"];
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Polls the `metadata` collection and runs the monthly jobs once the prices of a subdomain
+/// have been marked as updated.
+pub async fn watch(state: AppState) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        for subdomain in SUBDOMAINS {
+            if state.metadata.prices_flipped("searches", subdomain).await {
+                rerun_saved_searches(&state, subdomain).await;
+            }
+            if state.metadata.prices_flipped("alerts", subdomain).await {
+                evaluate_alerts(&state, subdomain).await;
+            }
+        }
+    }
+}
+
+/// Periodically removes expired sessions and tokens, for stores that cannot expire them on their
+/// own. MongoDB does this through the TTL indexes from `indexes::ensure_indexes`.
+pub async fn sweep(state: AppState) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        sweep_expired(&state).await;
+    }
+}
+
+pub async fn sweep_expired(state: &AppState) {
+    if let Err(error) = state.sessions.delete_expired_sessions().await {
+        tracing::error!(%error, "Could not remove expired sessions");
+    }
+    if let Err(error) = state.tokens.delete_expired_tokens().await {
+        tracing::error!(%error, "Could not remove expired tokens");
+    }
+}
+
+/// Writes the session refreshes queued by `Authenticate` in one batch per interval.
+pub async fn refresh_sessions(state: AppState) {
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    loop {
+        interval.tick().await;
+        flush_session_refreshes(&state).await;
+    }
+}
+
+pub async fn flush_session_refreshes(state: &AppState) {
+    let session_ids = state.refreshes.take();
+    if session_ids.is_empty() {
+        return;
+    }
+    if let Err(error) = state.sessions.update_expirations(&session_ids).await {
+        tracing::error!(%error, count = session_ids.len(), "Could not refresh sessions");
+    }
+}
+
+/// Strips the pagination of a listing pipeline, so that every match is returned.
+fn without_pagination(pipeline: Vec<Document>) -> Vec<Document> {
+    pipeline
+        .into_iter()
+        .filter(|stage| !stage.contains_key("$skip") && !stage.contains_key("$limit"))
+        .chain(std::iter::once(doc! { "$limit": MAX_SEARCH_MATCHES }))
+        .collect()
+}
+
+fn fresh_matches(previous: &[i64], current: &[i64]) -> Vec<i64> {
+    current
+        .iter()
+        .filter(|index| !previous.contains(index))
+        .copied()
+        .collect()
+}
+
+pub async fn match_saved_search(
+    state: &AppState,
+    search: &SavedSearch,
+    prices_updated: bool,
+) -> Result<Vec<i64>, AppError> {
+    let subdomain = Subdomain::from_name(&search.subdomain);
+    let user = state.users.get_user_by_id(&search.user_id).await;
+
+    let pipeline = search
+        .parameters
+        .to_pipeline(&subdomain, &user, prices_updated);
+    state
+        .products
+        .get_indices(without_pagination(pipeline))
+        .await
+}
+
+pub async fn rerun_saved_searches(state: &AppState, subdomain: &str) {
+    let searches = match state
+        .users
+        .get_saved_searches_for_subdomain(subdomain)
+        .await
+    {
+        Ok(searches) => searches,
+        Err(error) => {
+            tracing::error!(%error, subdomain, "Could not fetch saved searches");
+            return;
+        }
+    };
+
+    for search in searches {
+        // Recording no matches would replace the baseline, and report every product as fresh once
+        // the catalogue can be read again.
+        let matches = match match_saved_search(state, &search, true).await {
+            Ok(matches) => matches,
+            Err(error) => {
+                tracing::error!(
+                    %error,
+                    search_id = %search.search_id,
+                    "Could not match saved search"
+                );
+                continue;
+            }
+        };
+        let fresh = fresh_matches(&search.matches, &matches);
+        if let Err(error) = state
+            .users
+            .record_search_matches(&search.search_id, &matches, &fresh)
+            .await
+        {
+            tracing::error!(
+                %error,
+                search_id = %search.search_id,
+                "Could not record saved search matches"
+            );
+        }
+    }
+}
+
+/// Checks the alerts on the prices of `subdomain` against the updated prices. Alerts whose
+/// price fell to or below their threshold become pending until the user has seen them.
+pub async fn evaluate_alerts(state: &AppState, subdomain: &str) {
+    let alerts = match state
+        .users
+        .get_alerts_for_fields(AlertField::for_subdomain(subdomain))
+        .await
+    {
+        Ok(alerts) => alerts,
+        Err(error) => {
+            tracing::error!(%error, subdomain, "Could not fetch alerts");
+            return;
+        }
+    };
+
+    let mut indices: Vec<i64> = alerts.iter().map(|alert| alert.index).collect();
+    indices.sort_unstable();
+    indices.dedup();
+    // Only a product missing from a successful lookup is gone. Evaluating the alerts without the
+    // products would reset them, and fire those already fired again once the lookup succeeds.
+    let products: HashMap<i64, Product> =
+        match state.products.get_products_by_indices(&indices).await {
+            Ok(products) => products
+                .into_iter()
+                .map(|product| (product.index as i64, product))
+                .collect(),
+            Err(error) => {
+                tracing::error!(%error, subdomain, "Could not fetch products for alerts");
+                return;
+            }
+        };
+
+    for mut alert in alerts {
+        let value = products
+            .get(&alert.index)
+            .and_then(|product| alert.field.value(product));
+        if value == alert.value {
+            continue;
+        }
+        alert.evaluate(value);
+        if let Err(error) = state.users.record_alert(&alert).await {
+            tracing::error!(%error, alert_id = %alert.alert_id, "Could not record alert");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryStore;
+    use async_trait::async_trait;
+    use mongodb::bson::{DateTime, oid::ObjectId};
+    use shared::{
+        config::Config,
+        errors::AppError,
+        mail::{Mail, Mailer},
+        models::Alert,
+    };
+    use std::sync::Arc;
+
+    struct NoMailer;
+
+    #[async_trait]
+    impl Mailer for NoMailer {
+        async fn send(&self, _: &Mail) -> Result<(), AppError> {
+            Ok(())
+        }
+    }
+
+    fn alert(index: i64, field: AlertField, threshold: f64, value: Option<f64>) -> Alert {
+        Alert {
+            alert_id: ObjectId::new(),
+            user_id: ObjectId::new(),
+            index,
+            field,
+            threshold,
+            value,
+            triggered: None,
+            pending: false,
+            created: DateTime::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn evaluate_alerts_marks_crossed_thresholds_as_pending() {
+        let product = |index: i64, price: f64| {
+            doc! {
+                "index": index,
+                "name": "Vin",
+                "price": price,
+                "prices": [price],
+                "discount": 0.0,
+                "volume": 75.0,
+                "alcohol": 13.0,
+                "literprice": price / 0.75,
+                "url": "https://example.com",
+                "stores": [],
+                "category": "Rødvin",
+                "country": "Italia",
+                "taxfree": { "url": "", "price": price - 50.0, "discount": 0.0, "stores": [] },
+            }
+        };
+        let state = AppState::from_store(
+            MemoryStore::with_products(vec![product(1, 240.0), product(2, 300.0)]),
+            Config::default(),
+            Arc::new(NoMailer),
+        );
+
+        let crossed = alert(1, AlertField::Price, 250.0, Some(260.0));
+        let above = alert(2, AlertField::Price, 250.0, Some(310.0));
+        let still_below = alert(1, AlertField::Literprice, 400.0, Some(330.0));
+        let taxfree = alert(2, AlertField::Taxfree, 260.0, Some(270.0));
+        for alert in [&crossed, &above, &still_below, &taxfree] {
+            state.users.create_alert(alert).await.unwrap();
+        }
+
+        evaluate_alerts(&state, "vinmonopolet").await;
+        let evaluated = state
+            .users
+            .get_alerts_for_fields(&[AlertField::Price, AlertField::Literprice])
+            .await
+            .unwrap();
+        let pending: Vec<(i64, AlertField)> = evaluated
+            .iter()
+            .filter(|alert| alert.pending)
+            .map(|alert| (alert.index, alert.field))
+            .collect();
+        assert_eq!(pending, vec![(1, AlertField::Price)]);
+        assert_eq!(evaluated[1].value, Some(300.0));
+        assert_eq!(evaluated[2].value, Some(320.0));
+
+        let taxfree_alerts = state
+            .users
+            .get_alerts_for_fields(&[AlertField::Taxfree])
+            .await
+            .unwrap();
+        assert!(!taxfree_alerts[0].pending);
+        evaluate_alerts(&state, "taxfree").await;
+        let taxfree_alerts = state
+            .users
+            .get_alerts_for_fields(&[AlertField::Taxfree])
+            .await
+            .unwrap();
+        assert!(taxfree_alerts[0].pending);
+        assert_eq!(taxfree_alerts[0].value, Some(250.0));
+    }
+
+    #[tokio::test]
+    async fn alerts_are_left_alone_when_their_products_cannot_be_read() {
+        let state = AppState::from_store(
+            MemoryStore::new().with_unavailable_products(),
+            Config::default(),
+            Arc::new(NoMailer),
+        );
+        let fired = Alert {
+            triggered: Some(DateTime::now()),
+            ..alert(1, AlertField::Price, 250.0, Some(240.0))
+        };
+        state.users.create_alert(&fired).await.unwrap();
+
+        evaluate_alerts(&state, "vinmonopolet").await;
+        let alerts = state.users.get_alerts(&fired.user_id).await.unwrap();
+        assert_eq!(alerts[0].value, Some(240.0));
+        assert_eq!(alerts[0].triggered, fired.triggered);
+    }
+
+    #[tokio::test]
+    async fn saved_searches_keep_their_baseline_when_the_catalogue_fails() {
+        let state = AppState::from_store(
+            MemoryStore::new().with_unavailable_products(),
+            Config::default(),
+            Arc::new(NoMailer),
+        );
+        let search = SavedSearch {
+            search_id: ObjectId::new(),
+            user_id: ObjectId::new(),
+            name: "Barolo".to_string(),
+            subdomain: "vinmonopolet".to_string(),
+            parameters: Default::default(),
+            matches: vec![1, 2],
+            fresh: vec![2],
+            checked: DateTime::now(),
+        };
+        state.users.create_saved_search(&search).await.unwrap();
+
+        rerun_saved_searches(&state, "vinmonopolet").await;
+        let searches = state
+            .users
+            .get_saved_searches(&search.user_id)
+            .await
+            .unwrap();
+        assert_eq!(searches[0].matches, vec![1, 2]);
+        assert_eq!(searches[0].fresh, vec![2]);
+    }
+
+    #[test]
+    fn without_pagination_replaces_skip_and_limit() {
+        let pipeline = vec![
+            doc! { "$match": { "price": { "$gt": 0.0 } } },
+            doc! { "$sort": { "discount": 1 } },
+            doc! { "$skip": 30_i64 },
+            doc! { "$limit": 15_i64 },
+        ];
+        let pipeline = without_pagination(pipeline);
+        assert_eq!(pipeline.len(), 3);
+        assert!(pipeline[0].contains_key("$match"));
+        assert!(pipeline[1].contains_key("$sort"));
+        assert_eq!(pipeline[2].get_i64("$limit"), Ok(MAX_SEARCH_MATCHES));
+    }
+
+    #[test]
+    fn fresh_matches_only_keeps_new_indices() {
+        assert_eq!(fresh_matches(&[1, 2, 3], &[2, 3, 4, 5]), vec![4, 5]);
+        assert_eq!(fresh_matches(&[], &[1]), vec![1]);
+        assert!(fresh_matches(&[1, 2], &[1]).is_empty());
+    }
+}
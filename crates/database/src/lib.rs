@@ -1,4 +1,10 @@
+pub mod cache;
 pub mod connect;
+pub mod indexes;
+pub mod jobs;
+pub mod memory;
 pub mod metadata;
+pub mod mongo;
 pub mod products;
+pub mod search;
 pub mod users;
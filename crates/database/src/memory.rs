@@ -0,0 +1,1310 @@
+use async_trait::async_trait;
+use mongodb::bson::{Bson, DateTime, Document, from_document, oid::ObjectId, to_document};
+use regex::RegexBuilder;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+
+use crate::search;
+use shared::{
+    errors::AppError,
+    models::{
+        Alert, AlertField, FavouriteEntry, FavouriteList, MAX_FAVOURITE_LISTS, MAX_LIST_ENTRIES,
+        ONE_MONTH, Page, Product, SavedSearch, Session, Token, TokenKind, User,
+    },
+    repository::{
+        MetadataRepository, ProductRepository, SessionRepository, TokenRepository, UserRepository,
+    },
+};
+
+#[derive(Default)]
+struct Metadata {
+    prices: HashMap<String, bool>,
+    jobs: HashMap<String, bool>,
+    snapshots: HashMap<String, HashMap<String, f64>>,
+    visitors: HashMap<String, u64>,
+}
+
+/// The repositories held in memory, for tests and offline demos. Products are kept as raw
+/// documents so that the filters and pipelines built by `Parameters` apply to them as-is.
+#[derive(Default)]
+pub struct MemoryStore {
+    products: RwLock<Vec<Document>>,
+    users: RwLock<Vec<User>>,
+    sessions: RwLock<Vec<Session>>,
+    tokens: RwLock<Vec<Token>>,
+    searches: RwLock<Vec<SavedSearch>>,
+    alerts: RwLock<Vec<Alert>>,
+    metadata: RwLock<Metadata>,
+    /// Fails every catalogue query, as a lost database connection would.
+    products_unavailable: bool,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_products(products: Vec<Document>) -> Self {
+        MemoryStore {
+            products: RwLock::new(products),
+            ..Self::default()
+        }
+    }
+
+    /// Products from a JSON array, as exported from the `products` collection.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let values: Vec<serde_json::Value> =
+            serde_json::from_str(json).map_err(|error| error.to_string())?;
+        let products = values
+            .iter()
+            .map(to_document)
+            .collect::<Result<Vec<Document>, _>>()
+            .map_err(|error| error.to_string())?;
+        Ok(Self::with_products(products))
+    }
+
+    /// Makes the catalogue queries fail, to exercise how callers handle database errors.
+    pub fn with_unavailable_products(mut self) -> Self {
+        self.products_unavailable = true;
+        self
+    }
+
+    pub fn set_prices_updated(&self, subdomain: &str, updated: bool) {
+        self.metadata
+            .write()
+            .unwrap()
+            .prices
+            .insert(subdomain.to_string(), updated);
+    }
+
+    /// Applies `update` to a list of a user, and returns the list unless the user has no such
+    /// list or `update` declined to apply.
+    fn update_list(
+        &self,
+        user_id: &ObjectId,
+        list_id: &str,
+        update: impl FnOnce(&mut FavouriteList) -> bool,
+    ) -> Option<FavouriteList> {
+        let mut users = self.users.write().unwrap();
+        let list = users
+            .iter_mut()
+            .find(|user| user.user_id == *user_id)?
+            .lists
+            .iter_mut()
+            .find(|list| list.id == list_id)?;
+        update(list).then(|| list.clone())
+    }
+
+    fn aggregate(&self, pipeline: &[Document]) -> Result<Vec<Document>, AppError> {
+        if self.products_unavailable {
+            return Err(AppError::InternalServerError);
+        }
+        Ok(aggregate(self.products.read().unwrap().clone(), pipeline))
+    }
+}
+
+fn lookup<'a>(document: &'a Document, path: &str) -> Option<&'a Bson> {
+    let mut parts = path.split('.');
+    let mut current = document.get(parts.next()?)?;
+    for part in parts {
+        current = current.as_document()?.get(part)?;
+    }
+    Some(current)
+}
+
+fn number(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Double(value) => Some(*value),
+        Bson::Int32(value) => Some(*value as f64),
+        Bson::Int64(value) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+fn compare(a: &Bson, b: &Bson) -> Option<Ordering> {
+    if let (Some(a), Some(b)) = (number(a), number(b)) {
+        return a.partial_cmp(&b);
+    }
+    match (a, b) {
+        (Bson::String(a), Bson::String(b)) => Some(a.cmp(b)),
+        (Bson::Boolean(a), Bson::Boolean(b)) => Some(a.cmp(b)),
+        (Bson::DateTime(a), Bson::DateTime(b)) => Some(a.cmp(b)),
+        (Bson::Null, Bson::Null) => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+/// Array fields match if any of their elements does, as in MongoDB.
+fn candidates(value: &Bson) -> Vec<&Bson> {
+    match value {
+        Bson::Array(items) => items.iter().collect(),
+        value => vec![value],
+    }
+}
+
+fn equals(value: Option<&Bson>, expected: &Bson) -> bool {
+    match value {
+        None | Some(Bson::Null) => matches!(expected, Bson::Null),
+        Some(value) => {
+            value == expected
+                || candidates(value)
+                    .into_iter()
+                    .any(|item| compare(item, expected) == Some(Ordering::Equal))
+        }
+    }
+}
+
+fn operator(value: Option<&Bson>, op: &str, argument: &Bson, options: &str) -> bool {
+    let ordered = |accept: fn(Ordering) -> bool| {
+        value.is_some_and(|value| {
+            candidates(value)
+                .into_iter()
+                .any(|item| compare(item, argument).is_some_and(accept))
+        })
+    };
+
+    match op {
+        "$exists" => value.is_some() == argument.as_bool().unwrap_or(true),
+        "$eq" => equals(value, argument),
+        "$ne" => !equals(value, argument),
+        "$gt" => ordered(|o| o == Ordering::Greater),
+        "$gte" => ordered(|o| o != Ordering::Less),
+        "$lt" => ordered(|o| o == Ordering::Less),
+        "$lte" => ordered(|o| o != Ordering::Greater),
+        "$in" => argument
+            .as_array()
+            .is_some_and(|items| items.iter().any(|item| equals(value, item))),
+        "$nin" => argument
+            .as_array()
+            .is_none_or(|items| !items.iter().any(|item| equals(value, item))),
+        "$regex" => {
+            let (Some(pattern), Some(value)) = (argument.as_str(), value) else {
+                return false;
+            };
+            let Ok(re) = RegexBuilder::new(pattern)
+                .case_insensitive(options.contains('i'))
+                .build()
+            else {
+                return false;
+            };
+            candidates(value)
+                .into_iter()
+                .any(|item| item.as_str().is_some_and(|s| re.is_match(s)))
+        }
+        "$options" => true,
+        _ => false,
+    }
+}
+
+/// Whether `document` satisfies a MongoDB query `filter`, for the operators used by `Parameters`.
+pub fn matches(document: &Document, filter: &Document) -> bool {
+    filter.iter().all(|(key, condition)| match key.as_str() {
+        "$and" => condition.as_array().is_some_and(|filters| {
+            filters
+                .iter()
+                .all(|f| f.as_document().is_some_and(|f| matches(document, f)))
+        }),
+        "$or" => condition.as_array().is_some_and(|filters| {
+            filters
+                .iter()
+                .any(|f| f.as_document().is_some_and(|f| matches(document, f)))
+        }),
+        _ => {
+            let value = lookup(document, key);
+            match condition {
+                Bson::Document(ops) if ops.keys().all(|op| op.starts_with('$')) => {
+                    let options = ops.get_str("$options").unwrap_or("");
+                    ops.iter()
+                        .all(|(op, argument)| operator(value, op, argument, options))
+                }
+                expected => equals(value, expected),
+            }
+        }
+    })
+}
+
+/// Runs the pipeline stages used by the listing: `$search`, `$match`, `$sort`, `$skip` and
+/// `$limit`. Other stages, such as `$project`, leave the documents untouched.
+pub fn aggregate(mut documents: Vec<Document>, pipeline: &[Document]) -> Vec<Document> {
+    for stage in pipeline {
+        if let Ok(filter) = stage.get_document("$match") {
+            documents.retain(|document| matches(document, filter));
+        } else if let Ok(search) = stage.get_document("$search") {
+            let query = search::query_text(search).unwrap_or_default();
+            let ranked = search::rank(
+                &query,
+                documents.iter().filter_map(|document| {
+                    let index = number(document.get("index")?)? as i64;
+                    Some((index, document.get_str("name").ok()?))
+                }),
+            );
+            let position: HashMap<i64, usize> = ranked
+                .into_iter()
+                .enumerate()
+                .map(|(position, index)| (index, position))
+                .collect();
+            let rank = |document: &Document| {
+                let index = number(document.get("index")?)? as i64;
+                position.get(&index).copied()
+            };
+            documents.retain(|document| rank(document).is_some());
+            documents.sort_by_key(|document| rank(document));
+        } else if let Ok(sort) = stage.get_document("$sort") {
+            documents.sort_by(|a, b| {
+                sort.iter()
+                    .map(|(field, direction)| {
+                        let ordering = match (lookup(a, field), lookup(b, field)) {
+                            (Some(a), Some(b)) => compare(a, b).unwrap_or(Ordering::Equal),
+                            (None, Some(_)) => Ordering::Less,
+                            (Some(_), None) => Ordering::Greater,
+                            (None, None) => Ordering::Equal,
+                        };
+                        if number(direction).unwrap_or(1.0) < 0.0 {
+                            ordering.reverse()
+                        } else {
+                            ordering
+                        }
+                    })
+                    .find(|ordering| *ordering != Ordering::Equal)
+                    .unwrap_or(Ordering::Equal)
+            });
+        } else if let Some(skip) = stage.get("$skip").and_then(number) {
+            documents = documents.into_iter().skip(skip.max(0.0) as usize).collect();
+        } else if let Some(limit) = stage.get("$limit").and_then(number) {
+            documents.truncate(limit.max(0.0) as usize);
+        }
+    }
+    documents
+}
+
+#[async_trait]
+impl ProductRepository for MemoryStore {
+    async fn get_products(&self, pipeline: Vec<Document>) -> Result<Vec<Product>, AppError> {
+        Ok(self
+            .aggregate(&pipeline)?
+            .into_iter()
+            .filter_map(|document| from_document(document).ok())
+            .collect())
+    }
+
+    async fn get_page(
+        &self,
+        query: Vec<Document>,
+        paging: Vec<Document>,
+    ) -> Result<Page<Product>, AppError> {
+        let matched = self.aggregate(&query)?;
+        let total = matched.len() as u64;
+        let items = aggregate(matched, &paging)
+            .into_iter()
+            .filter_map(|document| from_document(document).ok())
+            .collect();
+        Ok(crate::products::to_page(items, total))
+    }
+
+    async fn get_indices(&self, pipeline: Vec<Document>) -> Result<Vec<i64>, AppError> {
+        Ok(self
+            .aggregate(&pipeline)?
+            .iter()
+            .filter_map(|document| document.get("index").and_then(number))
+            .map(|index| index as i64)
+            .collect())
+    }
+
+    async fn get_distinct(&self, field: &str, is_taxfree: bool) -> Vec<String> {
+        let products = self.products.read().unwrap();
+        let mut values: Vec<String> = products
+            .iter()
+            .filter(|document| !is_taxfree || !equals(lookup(document, "taxfree"), &Bson::Null))
+            .filter_map(|document| lookup(document, field))
+            .flat_map(candidates)
+            .filter_map(|value| value.as_str().map(str::to_string))
+            .collect();
+        values.sort();
+        values.dedup();
+        values
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryStore {
+    async fn get_user_by_name(&self, username: &str) -> Option<User> {
+        self.users
+            .read()
+            .unwrap()
+            .iter()
+            .find(|user| user.username == username)
+            .cloned()
+    }
+
+    async fn get_user_by_id(&self, user_id: &ObjectId) -> Option<User> {
+        self.users
+            .read()
+            .unwrap()
+            .iter()
+            .find(|user| user.user_id == *user_id)
+            .cloned()
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> Option<User> {
+        self.users
+            .read()
+            .unwrap()
+            .iter()
+            .find(|user| user.email.to_lowercase() == email.to_lowercase())
+            .cloned()
+    }
+
+    async fn get_user_by_list_slug(&self, slug: &str) -> Option<User> {
+        self.users
+            .read()
+            .unwrap()
+            .iter()
+            .find(|user| {
+                user.lists
+                    .iter()
+                    .any(|list| list.slug.as_deref() == Some(slug))
+            })
+            .cloned()
+    }
+
+    async fn get_notified_users(&self) -> Result<Vec<User>, AppError> {
+        Ok(self
+            .users
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|user| user.notify && user.verified)
+            .cloned()
+            .collect())
+    }
+
+    async fn create_user(&self, user: &User) -> Result<(), AppError> {
+        self.users.write().unwrap().push(user.clone());
+        Ok(())
+    }
+
+    async fn toggle_favourite(&self, user_id: &ObjectId, index: &i64) -> Result<(), AppError> {
+        let mut users = self.users.write().unwrap();
+        let user = users
+            .iter_mut()
+            .find(|user| user.user_id == *user_id)
+            .ok_or(AppError::NotFound)?;
+
+        if user.favourites.contains(index) {
+            user.favourites.retain(|favourite| favourite != index);
+        } else {
+            user.favourites.push(*index);
+        }
+        Ok(())
+    }
+
+    async fn notification(&self, user_id: &ObjectId, notify: bool) -> Result<(), AppError> {
+        let mut users = self.users.write().unwrap();
+        let user = users
+            .iter_mut()
+            .find(|user| user.user_id == *user_id)
+            .ok_or(AppError::NotFound)?;
+
+        user.notify = notify;
+        Ok(())
+    }
+
+    async fn update_password(&self, user_id: &ObjectId, password: &str) -> Result<(), AppError> {
+        let mut users = self.users.write().unwrap();
+        let user = users
+            .iter_mut()
+            .find(|user| user.user_id == *user_id)
+            .ok_or(AppError::NotFound)?;
+
+        user.password = password.to_string();
+        Ok(())
+    }
+
+    async fn set_verified(&self, user_id: &ObjectId) -> Result<(), AppError> {
+        let mut users = self.users.write().unwrap();
+        let user = users
+            .iter_mut()
+            .find(|user| user.user_id == *user_id)
+            .ok_or(AppError::NotFound)?;
+
+        user.verified = true;
+        Ok(())
+    }
+
+    async fn take_user_to_verify(&self) -> Result<Option<User>, AppError> {
+        // Users created in memory always have `verified` set.
+        Ok(None)
+    }
+
+    async fn update_username(&self, user_id: &ObjectId, username: &str) -> Result<(), AppError> {
+        let mut users = self.users.write().unwrap();
+        let user = users
+            .iter_mut()
+            .find(|user| user.user_id == *user_id)
+            .ok_or(AppError::NotFound)?;
+
+        user.username = username.to_string();
+        Ok(())
+    }
+
+    async fn create_list(
+        &self,
+        user_id: &ObjectId,
+        list: &FavouriteList,
+    ) -> Result<bool, AppError> {
+        let mut users = self.users.write().unwrap();
+        let Some(user) = users.iter_mut().find(|user| user.user_id == *user_id) else {
+            return Ok(false);
+        };
+        if user.lists.len() >= MAX_FAVOURITE_LISTS {
+            return Ok(false);
+        }
+
+        user.lists.push(list.clone());
+        Ok(true)
+    }
+
+    async fn rename_list(
+        &self,
+        user_id: &ObjectId,
+        list_id: &str,
+        name: &str,
+    ) -> Result<Option<FavouriteList>, AppError> {
+        Ok(self.update_list(user_id, list_id, |list| {
+            list.name = name.to_string();
+            true
+        }))
+    }
+
+    async fn delete_list(&self, user_id: &ObjectId, list_id: &str) -> Result<bool, AppError> {
+        let mut users = self.users.write().unwrap();
+        let Some(user) = users.iter_mut().find(|user| user.user_id == *user_id) else {
+            return Ok(false);
+        };
+
+        let length = user.lists.len();
+        user.lists.retain(|list| list.id != list_id);
+        Ok(user.lists.len() < length)
+    }
+
+    async fn share_list(
+        &self,
+        user_id: &ObjectId,
+        list_id: &str,
+        slug: &str,
+    ) -> Result<Option<FavouriteList>, AppError> {
+        Ok(self.update_list(user_id, list_id, |list| {
+            list.slug.get_or_insert_with(|| slug.to_string());
+            true
+        }))
+    }
+
+    async fn unshare_list(&self, user_id: &ObjectId, list_id: &str) -> Result<bool, AppError> {
+        Ok(self
+            .update_list(user_id, list_id, |list| {
+                list.slug = None;
+                true
+            })
+            .is_some())
+    }
+
+    async fn put_entry(
+        &self,
+        user_id: &ObjectId,
+        list_id: &str,
+        entry: &FavouriteEntry,
+    ) -> Result<Option<FavouriteList>, AppError> {
+        Ok(self.update_list(user_id, list_id, |list| {
+            let full = list.entries.len() >= MAX_LIST_ENTRIES;
+            match list.entries.iter_mut().find(|e| e.index == entry.index) {
+                Some(existing) => {
+                    existing.note = entry.note.clone();
+                    existing.target_price = entry.target_price;
+                }
+                None if full => return false,
+                None => list.entries.push(entry.clone()),
+            }
+            true
+        }))
+    }
+
+    async fn remove_entry(
+        &self,
+        user_id: &ObjectId,
+        list_id: &str,
+        index: i64,
+    ) -> Result<bool, AppError> {
+        Ok(self
+            .update_list(user_id, list_id, |list| list.remove(index).is_some())
+            .is_some())
+    }
+
+    async fn move_entry(
+        &self,
+        user_id: &ObjectId,
+        from: &str,
+        to: &str,
+        entry: &FavouriteEntry,
+    ) -> Result<bool, AppError> {
+        let mut users = self.users.write().unwrap();
+        let Some(user) = users.iter_mut().find(|user| user.user_id == *user_id) else {
+            return Ok(false);
+        };
+        let (Some(source), Some(target)) = (
+            user.lists.iter().position(|list| list.id == from),
+            user.lists.iter().position(|list| list.id == to),
+        ) else {
+            return Ok(false);
+        };
+        let full = user.lists[target].entries.len() >= MAX_LIST_ENTRIES;
+        if full && user.lists[target].entry(entry.index).is_none() {
+            return Ok(false);
+        }
+        if user.lists[source].remove(entry.index).is_none() {
+            return Ok(false);
+        }
+
+        user.lists[target].upsert(entry.clone());
+        Ok(true)
+    }
+
+    async fn update_email(&self, user_id: &ObjectId, email: &str) -> Result<(), AppError> {
+        let mut users = self.users.write().unwrap();
+        let user = users
+            .iter_mut()
+            .find(|user| user.user_id == *user_id)
+            .ok_or(AppError::NotFound)?;
+
+        user.email = email.to_string();
+        user.verified = false;
+        Ok(())
+    }
+
+    async fn delete_user(&self, user_id: &ObjectId) -> Result<(), AppError> {
+        self.tokens
+            .write()
+            .unwrap()
+            .retain(|token| token.user_id != *user_id);
+        self.searches
+            .write()
+            .unwrap()
+            .retain(|search| search.user_id != *user_id);
+        self.alerts
+            .write()
+            .unwrap()
+            .retain(|alert| alert.user_id != *user_id);
+        self.users
+            .write()
+            .unwrap()
+            .retain(|user| user.user_id != *user_id);
+        Ok(())
+    }
+
+    async fn get_saved_searches(&self, user_id: &ObjectId) -> Result<Vec<SavedSearch>, AppError> {
+        Ok(self
+            .searches
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|search| search.user_id == *user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_saved_searches_for_subdomain(
+        &self,
+        subdomain: &str,
+    ) -> Result<Vec<SavedSearch>, AppError> {
+        Ok(self
+            .searches
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|search| search.subdomain == subdomain)
+            .cloned()
+            .collect())
+    }
+
+    async fn create_saved_search(&self, search: &SavedSearch) -> Result<(), AppError> {
+        self.searches.write().unwrap().push(search.clone());
+        Ok(())
+    }
+
+    async fn update_saved_search(&self, search: &SavedSearch) -> Result<(), AppError> {
+        let mut searches = self.searches.write().unwrap();
+        let existing = searches
+            .iter_mut()
+            .find(|s| s.search_id == search.search_id && s.user_id == search.user_id)
+            .ok_or(AppError::NotFound)?;
+
+        *existing = search.clone();
+        Ok(())
+    }
+
+    async fn record_search_matches(
+        &self,
+        search_id: &ObjectId,
+        matches: &[i64],
+        fresh: &[i64],
+    ) -> Result<(), AppError> {
+        let mut searches = self.searches.write().unwrap();
+        if let Some(search) = searches.iter_mut().find(|s| s.search_id == *search_id) {
+            search.matches = matches.to_vec();
+            search.fresh = fresh.to_vec();
+            search.checked = DateTime::now();
+        }
+        Ok(())
+    }
+
+    async fn delete_saved_search(
+        &self,
+        user_id: &ObjectId,
+        search_id: &ObjectId,
+    ) -> Result<(), AppError> {
+        let mut searches = self.searches.write().unwrap();
+        let before = searches.len();
+        searches.retain(|s| !(s.search_id == *search_id && s.user_id == *user_id));
+
+        if searches.len() == before {
+            return Err(AppError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn get_alerts(&self, user_id: &ObjectId) -> Result<Vec<Alert>, AppError> {
+        Ok(self
+            .alerts
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|alert| alert.user_id == *user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_alerts_for_fields(&self, fields: &[AlertField]) -> Result<Vec<Alert>, AppError> {
+        Ok(self
+            .alerts
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|alert| fields.contains(&alert.field))
+            .cloned()
+            .collect())
+    }
+
+    async fn create_alert(&self, alert: &Alert) -> Result<(), AppError> {
+        self.alerts.write().unwrap().push(alert.clone());
+        Ok(())
+    }
+
+    async fn record_alert(&self, alert: &Alert) -> Result<(), AppError> {
+        let mut alerts = self.alerts.write().unwrap();
+        if let Some(existing) = alerts.iter_mut().find(|a| a.alert_id == alert.alert_id) {
+            existing.value = alert.value;
+            existing.triggered = alert.triggered;
+            existing.pending = alert.pending;
+        }
+        Ok(())
+    }
+
+    async fn dismiss_alerts(&self, user_id: &ObjectId) -> Result<(), AppError> {
+        for alert in self.alerts.write().unwrap().iter_mut() {
+            if alert.user_id == *user_id {
+                alert.pending = false;
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete_alert(&self, user_id: &ObjectId, alert_id: &ObjectId) -> Result<(), AppError> {
+        let mut alerts = self.alerts.write().unwrap();
+        let before = alerts.len();
+        alerts.retain(|a| !(a.alert_id == *alert_id && a.user_id == *user_id));
+
+        if alerts.len() == before {
+            return Err(AppError::NotFound);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionRepository for MemoryStore {
+    async fn store_session(&self, session: Session) -> Result<(), AppError> {
+        self.sessions.write().unwrap().push(session);
+        Ok(())
+    }
+
+    async fn get_session(&self, session_id: &str) -> Result<Session, AppError> {
+        self.sessions
+            .read()
+            .unwrap()
+            .iter()
+            .find(|session| session.session_id == session_id)
+            .cloned()
+            .ok_or(AppError::NotFound)
+    }
+
+    async fn get_sessions_for_user(&self, user_id: &ObjectId) -> Result<Vec<Session>, AppError> {
+        Ok(self
+            .sessions
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|session| session.user_id == *user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn update_expirations(&self, session_ids: &[String]) -> Result<(), AppError> {
+        let expires_after =
+            DateTime::from_system_time(SystemTime::now() + Duration::from_secs(ONE_MONTH));
+        let mut sessions = self.sessions.write().unwrap();
+        for session in sessions
+            .iter_mut()
+            .filter(|s| session_ids.contains(&s.session_id))
+        {
+            session.expires_after = expires_after;
+            session.last_seen = Some(DateTime::now());
+        }
+        Ok(())
+    }
+
+    async fn delete_session(&self, session_id: &str) -> Result<(), AppError> {
+        self.sessions
+            .write()
+            .unwrap()
+            .retain(|session| session.session_id != session_id);
+        Ok(())
+    }
+
+    async fn delete_sessions_for_user(&self, user_id: &ObjectId) -> Result<(), AppError> {
+        self.sessions
+            .write()
+            .unwrap()
+            .retain(|session| session.user_id != *user_id);
+        Ok(())
+    }
+
+    async fn delete_session_by_id(
+        &self,
+        user_id: &ObjectId,
+        id: &ObjectId,
+    ) -> Result<(), AppError> {
+        let mut sessions = self.sessions.write().unwrap();
+        let before = sessions.len();
+        sessions.retain(|s| !(s.id == *id && s.user_id == *user_id));
+
+        if sessions.len() == before {
+            return Err(AppError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn delete_other_sessions(
+        &self,
+        user_id: &ObjectId,
+        session_id: &str,
+    ) -> Result<(), AppError> {
+        self.sessions
+            .write()
+            .unwrap()
+            .retain(|s| s.user_id != *user_id || s.session_id == session_id);
+        Ok(())
+    }
+
+    async fn delete_expired_sessions(&self) -> Result<u64, AppError> {
+        let mut sessions = self.sessions.write().unwrap();
+        let before = sessions.len();
+        let now = DateTime::now();
+        sessions.retain(|session| session.expires_after > now);
+        Ok((before - sessions.len()) as u64)
+    }
+
+    async fn count_active_sessions(&self) -> Result<u64, AppError> {
+        let now = DateTime::now();
+        let sessions = self.sessions.read().unwrap();
+        Ok(sessions.iter().filter(|s| s.expires_after > now).count() as u64)
+    }
+}
+
+#[async_trait]
+impl TokenRepository for MemoryStore {
+    async fn store_token(&self, token: Token) -> Result<(), AppError> {
+        self.tokens.write().unwrap().push(token);
+        Ok(())
+    }
+
+    async fn consume_token(&self, kind: TokenKind, hash: &str) -> Result<Option<Token>, AppError> {
+        let mut tokens = self.tokens.write().unwrap();
+        let now = DateTime::now();
+        let position = tokens
+            .iter()
+            .position(|t| t.kind == kind && t.hash == hash && t.expires_after > now);
+        Ok(position.map(|position| tokens.remove(position)))
+    }
+
+    async fn delete_tokens_for_user(
+        &self,
+        user_id: &ObjectId,
+        kind: TokenKind,
+    ) -> Result<(), AppError> {
+        self.tokens
+            .write()
+            .unwrap()
+            .retain(|token| !(token.user_id == *user_id && token.kind == kind));
+        Ok(())
+    }
+
+    async fn delete_expired_tokens(&self) -> Result<u64, AppError> {
+        let mut tokens = self.tokens.write().unwrap();
+        let before = tokens.len();
+        let now = DateTime::now();
+        tokens.retain(|token| token.expires_after > now);
+        Ok((before - tokens.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl MetadataRepository for MemoryStore {
+    async fn increment_visitor(&self, month: &str, subdomain: &str, fresh: bool) {
+        let current = if fresh { "fresh" } else { "newpage" };
+        let mut metadata = self.metadata.write().unwrap();
+        for key in [
+            format!("{}.total", current),
+            format!("{}.month.{}.{}", current, month, subdomain),
+        ] {
+            *metadata.visitors.entry(key).or_default() += 1;
+        }
+    }
+
+    async fn get_prices_updated(&self, subdomain: &str) -> bool {
+        self.metadata
+            .read()
+            .unwrap()
+            .prices
+            .get(subdomain)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    async fn prices_flipped(&self, job: &str, subdomain: &str) -> bool {
+        let mut metadata = self.metadata.write().unwrap();
+        let updated = metadata.prices.get(subdomain).copied().unwrap_or(false);
+        let key = format!("{}.{}", job, subdomain);
+        // As in MongoDB, a job without a recorded status only records it.
+        match metadata.jobs.insert(key, updated) {
+            Some(previous) => updated && !previous,
+            None => false,
+        }
+    }
+
+    async fn get_price_snapshot(&self, subdomain: &str) -> HashMap<String, f64> {
+        self.metadata
+            .read()
+            .unwrap()
+            .snapshots
+            .get(subdomain)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    async fn set_price_snapshot(&self, subdomain: &str, prices: &HashMap<String, f64>) {
+        self.metadata
+            .write()
+            .unwrap()
+            .snapshots
+            .insert(subdomain.to_string(), prices.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+    use shared::{query::Parameters, subdomain::Subdomain};
+
+    fn product(index: i64, name: &str, category: &str, price: f64, discount: f64) -> Document {
+        doc! {
+            "index": index,
+            "name": name,
+            "price": price,
+            "prices": [price],
+            "discount": discount,
+            "volume": 75.0,
+            "alcohol": 13.5,
+            "literprice": price / 0.75,
+            "url": "https://example.com",
+            "stores": ["Oslo, Aker Brygge", "Bergen, Valkendorfsgate"],
+            "category": category,
+            "country": "Italia",
+            "updated": true,
+            "orderable": true,
+        }
+    }
+
+    fn store() -> MemoryStore {
+        let mut taxfree = product(4, "Taxfree Gin", "Brennevin", 400.0, 0.0);
+        taxfree.insert(
+            "taxfree",
+            doc! {
+                "url": "https://example.com/tax",
+                "price": 250.0,
+                "discount": -30.0,
+                "stores": ["Gardermoen"],
+                "valid": true,
+            },
+        );
+        MemoryStore::with_products(vec![
+            product(1, "Barolo Riserva", "Rødvin", 390.0, -12.0),
+            product(2, "Barolo Classico", "Rødvin", 450.0, -5.0),
+            product(3, "Chablis", "Hvitvin", 250.0, 3.0),
+            taxfree,
+        ])
+    }
+
+    #[test]
+    fn matches_supports_comparison_exists_and_regex_operators() {
+        let document = product(1, "Barolo", "Rødvin", 390.0, -12.0);
+        assert!(matches(
+            &document,
+            &doc! { "price": { "$gt": 0.0, "$lte": 400 } }
+        ));
+        assert!(!matches(&document, &doc! { "price": { "$lt": 100.0 } }));
+        assert!(matches(&document, &doc! { "year": { "$exists": false } }));
+        assert!(!matches(&document, &doc! { "year": { "$ne": Bson::Null } }));
+        assert!(matches(
+            &document,
+            &doc! { "index": { "$in": [5_i64, 1_i64] } }
+        ));
+        assert!(matches(
+            &document,
+            &doc! { "stores": { "$in": ["Oslo, Aker Brygge"] } }
+        ));
+        assert!(matches(
+            &document,
+            &doc! { "stores": { "$regex": "(^|[^a-zæøåA-ZÆØÅ])bergen([^a-zæøåA-ZÆØÅ]|$)", "$options": "i" } }
+        ));
+        assert!(!matches(
+            &document,
+            &doc! { "taxfree.stores": { "$exists": true } }
+        ));
+    }
+
+    #[tokio::test]
+    async fn listing_filters_from_parameters_apply_to_products() {
+        let store = store();
+
+        let parameters = Parameters {
+            category: Some("rødvin".to_string()),
+            price: Some(400.0),
+            ..Default::default()
+        };
+        let subdomain = Subdomain::Vinmonopolet;
+        let filter = parameters.to_filter(&subdomain, &None, true);
+        let matched = store
+            .get_products(vec![doc! { "$match": filter }])
+            .await
+            .unwrap();
+        assert_eq!(matched.len(), 1);
+
+        let pipeline = Parameters::default().to_pipeline(&subdomain, &None, true);
+        let names: Vec<String> = store
+            .get_products(pipeline)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "Barolo Riserva",
+                "Barolo Classico",
+                "Taxfree Gin",
+                "Chablis"
+            ]
+        );
+
+        let taxfree = Parameters::default().to_pipeline(&Subdomain::Taxfree, &None, true);
+        assert_eq!(store.get_indices(taxfree).await.unwrap(), vec![4]);
+    }
+
+    #[tokio::test]
+    async fn search_and_pagination_stages_apply_to_products() {
+        let store = store();
+
+        let parameters = Parameters {
+            search: Some("barolo".to_string()),
+            ..Default::default()
+        };
+        let pipeline = parameters.to_pipeline(&Subdomain::Vinmonopolet, &None, true);
+        assert_eq!(store.get_indices(pipeline).await.unwrap(), vec![1, 2]);
+
+        let typo = Parameters {
+            search: Some("barlo chablis".to_string()),
+            ..Default::default()
+        };
+        let pipeline = typo.to_pipeline(&Subdomain::Vinmonopolet, &None, true);
+        assert_eq!(store.get_indices(pipeline).await.unwrap(), vec![3, 1, 2]);
+
+        let page = aggregate(
+            store.products.read().unwrap().clone(),
+            &[
+                doc! { "$sort": { "price": -1 } },
+                doc! { "$skip": 1 },
+                doc! { "$limit": 2 },
+            ],
+        );
+        let indices: Vec<i64> = page.iter().map(|d| d.get_i64("index").unwrap()).collect();
+        assert_eq!(indices, vec![4, 1]);
+    }
+
+    #[tokio::test]
+    async fn get_page_counts_matches_before_paging() {
+        let store = store();
+        let subdomain = Subdomain::Vinmonopolet;
+
+        let first = Parameters::default();
+        let page = store
+            .get_page(
+                first.to_query(&subdomain, &None, true),
+                first.to_paging(&subdomain),
+            )
+            .await
+            .unwrap();
+        assert_eq!((page.items.len(), page.total, page.pages), (4, 4, 1));
+
+        let beyond = Parameters {
+            page: Some(2),
+            ..Default::default()
+        };
+        let page = store
+            .get_page(
+                beyond.to_query(&subdomain, &None, true),
+                beyond.to_paging(&subdomain),
+            )
+            .await
+            .unwrap();
+        assert!(page.items.is_empty());
+        assert_eq!(page.total, 4);
+
+        let search = Parameters {
+            search: Some("barolo".to_string()),
+            ..Default::default()
+        };
+        let page = store
+            .get_page(
+                search.to_query(&subdomain, &None, true),
+                search.to_paging(&subdomain),
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.total, 2);
+    }
+
+    #[tokio::test]
+    async fn distinct_preview_and_product_lookups() {
+        let store = store();
+        assert_eq!(
+            store.get_distinct("stores", false).await,
+            vec!["Bergen, Valkendorfsgate", "Oslo, Aker Brygge"]
+        );
+        assert_eq!(
+            store.get_distinct("taxfree.stores", true).await,
+            vec!["Gardermoen"]
+        );
+        assert_eq!(store.get_preview(false).await.unwrap().index, 1);
+        assert_eq!(store.get_preview(true).await.unwrap().index, 4);
+        assert_eq!(store.get_product(3).await.unwrap().name, "Chablis");
+        assert!(store.get_product(99).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn users_sessions_and_metadata_roundtrip() {
+        let store = MemoryStore::new();
+        let user = User {
+            user_id: ObjectId::new(),
+            username: "snubler".to_string(),
+            password: "hash".to_string(),
+            email: "snubler@example.com".to_string(),
+            favourites: vec![],
+            notify: true,
+            verified: false,
+            lists: vec![],
+        };
+        store.create_user(&user).await.unwrap();
+        assert!(store.get_notified_users().await.unwrap().is_empty());
+        store.set_verified(&user.user_id).await.unwrap();
+        assert_eq!(store.get_notified_users().await.unwrap().len(), 1);
+        assert_eq!(
+            store
+                .get_user_by_email("Snubler@Example.com")
+                .await
+                .unwrap()
+                .user_id,
+            user.user_id
+        );
+        store.toggle_favourite(&user.user_id, &7).await.unwrap();
+        assert_eq!(store.favourites(&user.user_id).await.unwrap(), vec![7]);
+        store.toggle_favourite(&user.user_id, &7).await.unwrap();
+        assert!(store.favourites(&user.user_id).await.unwrap().is_empty());
+
+        store
+            .store_session(Session {
+                id: ObjectId::new(),
+                user_id: user.user_id,
+                session_id: "abc".to_string(),
+                expires_after: DateTime::now(),
+                user_agent: None,
+                ip: None,
+                created_at: Some(DateTime::now()),
+                last_seen: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_session("abc").await.unwrap().user_id,
+            user.user_id
+        );
+        store.delete_sessions_for_user(&user.user_id).await.unwrap();
+        assert!(matches!(
+            store.get_session("abc").await,
+            Err(AppError::NotFound)
+        ));
+
+        assert!(!store.prices_flipped("job", "vinmonopolet").await);
+        store.set_prices_updated("vinmonopolet", true);
+        assert!(store.prices_flipped("job", "vinmonopolet").await);
+        assert!(!store.prices_flipped("job", "vinmonopolet").await);
+        // A job deployed after this month's update waits for the next one.
+        assert!(!store.prices_flipped("new", "vinmonopolet").await);
+        store.set_prices_updated("vinmonopolet", false);
+        assert!(!store.prices_flipped("new", "vinmonopolet").await);
+        store.set_prices_updated("vinmonopolet", true);
+        assert!(store.prices_flipped("new", "vinmonopolet").await);
+    }
+
+    #[tokio::test]
+    async fn list_entries_keep_when_added_and_are_capped() {
+        let store = MemoryStore::new();
+        let list = |id: &str, entries: Vec<FavouriteEntry>| FavouriteList {
+            id: id.to_string(),
+            name: id.to_string(),
+            entries,
+            slug: None,
+        };
+        let entry = |index: i64, note: Option<&str>| FavouriteEntry {
+            index,
+            note: note.map(str::to_string),
+            target_price: None,
+            added: DateTime::from_millis(index),
+        };
+        let full: Vec<FavouriteEntry> = (0..MAX_LIST_ENTRIES as i64)
+            .map(|index| entry(index, None))
+            .collect();
+        let user = User {
+            user_id: ObjectId::new(),
+            username: "snubler".to_string(),
+            password: "hash".to_string(),
+            email: "snubler@example.com".to_string(),
+            favourites: vec![],
+            notify: false,
+            verified: true,
+            lists: vec![list("full", full), list("empty", vec![])],
+        };
+        store.create_user(&user).await.unwrap();
+        let id = &user.user_id;
+
+        let updated = store.put_entry(id, "full", &entry(-1, None)).await.unwrap();
+        assert!(updated.is_none());
+        let mut edited = entry(3, Some("Til osten"));
+        edited.added = DateTime::now();
+        let updated = store.put_entry(id, "full", &edited).await.unwrap().unwrap();
+        let updated = updated.entry(3).unwrap();
+        assert_eq!(updated.note.as_deref(), Some("Til osten"));
+        assert_eq!(updated.added, DateTime::from_millis(3));
+
+        assert!(
+            store
+                .move_entry(id, "full", "empty", &entry(3, None))
+                .await
+                .unwrap()
+        );
+        assert!(
+            !store
+                .move_entry(id, "full", "empty", &entry(3, None))
+                .await
+                .unwrap()
+        );
+        assert!(
+            store
+                .put_entry(id, "full", &entry(-1, None))
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            !store
+                .move_entry(id, "empty", "full", &entry(3, None))
+                .await
+                .unwrap()
+        );
+        assert!(
+            store
+                .put_entry(id, "missing", &entry(1, None))
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_and_tokens_are_swept() {
+        let store = MemoryStore::new();
+        let user_id = ObjectId::new();
+        let past = DateTime::from_millis(DateTime::now().timestamp_millis() - 1000);
+        let future = DateTime::from_millis(DateTime::now().timestamp_millis() + 60_000);
+
+        for (session_id, expires_after) in [("old", past), ("new", future)] {
+            store
+                .store_session(Session {
+                    id: ObjectId::new(),
+                    user_id,
+                    session_id: session_id.to_string(),
+                    expires_after,
+                    user_agent: None,
+                    ip: None,
+                    created_at: None,
+                    last_seen: None,
+                })
+                .await
+                .unwrap();
+        }
+        store
+            .store_token(Token {
+                user_id,
+                kind: TokenKind::Reset,
+                hash: "hash".to_string(),
+                expires_after: past,
+            })
+            .await
+            .unwrap();
+
+        assert!(
+            store
+                .consume_token(TokenKind::Reset, "hash")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(store.count_active_sessions().await.unwrap(), 1);
+        assert_eq!(store.delete_expired_sessions().await.unwrap(), 1);
+        assert_eq!(store.delete_expired_tokens().await.unwrap(), 1);
+        assert!(store.get_session("old").await.is_err());
+        assert!(store.get_session("new").await.is_ok());
+    }
+}
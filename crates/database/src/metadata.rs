@@ -1,13 +1,14 @@
 use mongodb::{
     Collection, Database,
-    bson::{Document, doc, from_bson},
+    bson::{Document, doc, from_bson, to_document},
     options::UpdateOptions,
 };
+use std::collections::HashMap;
 
 pub async fn increment_visitor(db: &Database, month: &str, subdomain: &str, fresh: bool) {
     let collection: Collection<Document> = db.collection("metadata");
     let current = if fresh { "fresh" } else { "newpage" };
-    let _ = collection
+    if let Err(error) = collection
         .update_one(
             doc! { "id": "visitors" },
             doc! {
@@ -18,21 +19,132 @@ pub async fn increment_visitor(db: &Database, month: &str, subdomain: &str, fres
             },
         )
         .with_options(UpdateOptions::builder().upsert(true).build())
-        .await;
+        .await
+    {
+        tracing::warn!(%error, "Could not count visitor");
+    }
 }
 
-pub async fn get_prices_updated(db: &Database, subdomain: &str) -> bool {
+/// Whether the prices of `subdomain` have been updated this month. A missing status is read as not
+/// updated, but a failed read is an error, so that it is not mistaken for the prices reverting.
+pub async fn get_prices_updated(db: &Database, subdomain: &str) -> mongodb::error::Result<bool> {
     let collection: Collection<Document> = db.collection("metadata");
-    let Ok(Some(doc)) = collection.find_one(doc! { "id": "stock" }).await else {
-        return false;
+    let Some(doc) = collection.find_one(doc! { "id": "stock" }).await? else {
+        return Ok(false);
     };
-    doc.get_document("prices")
+    Ok(doc
+        .get_document("prices")
         .ok()
         .and_then(|prices| prices.get_bool(subdomain).ok())
-        .unwrap_or(false)
+        .unwrap_or(false))
+}
+
+/// Records the price status of `subdomain` under `jobs.<job>.<subdomain>`, and returns whether it
+/// flipped to updated. A job without a recorded status, as on its first deploy, only records it,
+/// since the update it would run for may be long past. Nothing is recorded when the status cannot
+/// be read, as a flip from a failed read would rerun the job on the next successful one.
+pub async fn prices_flipped(db: &Database, job: &str, subdomain: &str) -> bool {
+    let collection: Collection<Document> = db.collection("metadata");
+    let updated = match get_prices_updated(db, subdomain).await {
+        Ok(updated) => updated,
+        Err(error) => {
+            tracing::error!(%error, job, subdomain, "Could not fetch price status");
+            return false;
+        }
+    };
+    let key = format!("{}.{}", job, subdomain);
+
+    if let Err(error) = collection
+        .update_one(
+            doc! { "id": "jobs" },
+            doc! { "$setOnInsert": { "id": "jobs" } },
+        )
+        .with_options(UpdateOptions::builder().upsert(true).build())
+        .await
+    {
+        tracing::error!(%error, job, subdomain, "Could not initialise job status");
+        return false;
+    }
+
+    match collection
+        .update_one(
+            doc! { "id": "jobs", &key: { "$exists": false } },
+            doc! { "$set": { &key: updated } },
+        )
+        .await
+    {
+        Ok(result) if result.modified_count == 1 => {
+            tracing::info!(job, subdomain, updated, "Recorded initial job status");
+            return false;
+        }
+        Ok(_) => {}
+        Err(error) => {
+            tracing::error!(%error, job, subdomain, "Could not initialise job status");
+            return false;
+        }
+    }
+
+    match collection
+        .update_one(
+            doc! { "id": "jobs", &key: { "$ne": updated } },
+            doc! { "$set": { &key: updated } },
+        )
+        .await
+    {
+        Ok(result) => updated && result.modified_count == 1,
+        Err(error) => {
+            tracing::error!(%error, job, subdomain, "Could not update job status");
+            false
+        }
+    }
+}
+
+pub async fn get_price_snapshot(db: &Database, subdomain: &str) -> HashMap<String, f64> {
+    let collection: Collection<Document> = db.collection("metadata");
+    let doc = match collection.find_one(doc! { "id": "snapshot" }).await {
+        Ok(Some(doc)) => doc,
+        Ok(None) => return HashMap::new(),
+        Err(error) => {
+            tracing::error!(%error, subdomain, "Could not fetch price snapshot");
+            return HashMap::new();
+        }
+    };
+    doc.get_document(subdomain)
+        .map(|prices| {
+            prices
+                .iter()
+                .filter_map(|(index, price)| Some((index.clone(), price.as_f64()?)))
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
-pub async fn get_distinct(db: &Database, field: &str, is_taxfree: bool) -> Vec<String> {
+pub async fn set_price_snapshot(db: &Database, subdomain: &str, prices: &HashMap<String, f64>) {
+    let collection: Collection<Document> = db.collection("metadata");
+    let prices = match to_document(prices) {
+        Ok(prices) => prices,
+        Err(error) => {
+            tracing::error!(%error, subdomain, "Could not serialize price snapshot");
+            return;
+        }
+    };
+    if let Err(error) = collection
+        .update_one(
+            doc! { "id": "snapshot" },
+            doc! { "$set": { subdomain: prices } },
+        )
+        .with_options(UpdateOptions::builder().upsert(true).build())
+        .await
+    {
+        tracing::error!(%error, subdomain, "Could not store price snapshot");
+    }
+}
+
+pub async fn get_distinct(
+    db: &Database,
+    field: &str,
+    is_taxfree: bool,
+) -> mongodb::error::Result<Vec<String>> {
     let collection: Collection<Document> = db.collection("products");
 
     let mut filter = doc! { field: { "$exists": true } };
@@ -40,18 +152,11 @@ pub async fn get_distinct(db: &Database, field: &str, is_taxfree: bool) -> Vec<S
         filter.insert("taxfree", doc! { "$exists": true, "$ne": null });
     }
 
-    match collection.distinct(field, filter).await {
-        Ok(cursor) => cursor
-            .into_iter()
-            .map(|bson| from_bson::<String>(bson))
-            .collect::<Result<Vec<String>, _>>()
-            .unwrap_or_else(|e| {
-                eprintln!("Deserialization error: {:?}", e);
-                vec![]
-            }),
-        Err(error) => {
-            println!("No results found: {:?}", error);
-            vec![]
-        }
-    }
+    let values = collection
+        .distinct(field, filter)
+        .await?
+        .into_iter()
+        .map(from_bson::<String>)
+        .collect::<Result<Vec<String>, _>>()?;
+    Ok(values)
 }
@@ -0,0 +1,698 @@
+use async_trait::async_trait;
+use mongodb::{
+    Database,
+    bson::{Document, doc, oid::ObjectId},
+    error::ErrorKind,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Instant,
+};
+
+use crate::{
+    cache::{self, QueryCache, TtlMap},
+    metadata, products, search, users,
+};
+use shared::{
+    config::Search,
+    errors::AppError,
+    models::{
+        Alert, AlertField, FavouriteEntry, FavouriteList, Page, Product, SavedSearch, Session,
+        Token, TokenKind, User,
+    },
+    repository::{
+        MetadataRepository, ProductRepository, SessionRepository, TokenRepository, UserRepository,
+    },
+};
+
+/// Records how long the query took, labelled by the `database` function running it.
+async fn timed<T>(function: &'static str, query: impl Future<Output = T>) -> T {
+    let start = Instant::now();
+    let result = query.await;
+    metrics::histogram!("mongo_query_duration_seconds", "function" => function)
+        .record(start.elapsed().as_secs_f64());
+    result
+}
+
+/// Returns the entry for `key`, or runs the query and keeps its result. Failures are not kept, so
+/// that the next request tries again.
+async fn cached<V: Clone, E>(
+    map: &TtlMap<V>,
+    key: String,
+    query: impl Future<Output = Result<V, E>>,
+) -> Result<V, E> {
+    if let Some(value) = map.get(&key) {
+        return Ok(value);
+    }
+    let value = query.await?;
+    map.insert(key, value.clone());
+    Ok(value)
+}
+
+/// Server error codes meaning that Atlas Search is not available on the deployment: an unknown
+/// `$search` stage, and search not being enabled.
+const SEARCH_UNAVAILABLE: [i32; 2] = [40324, 31082];
+
+/// Whether `error` says that Atlas Search will not answer on this deployment, unlike e.g. a query
+/// that ran out of time.
+fn search_unavailable(error: &mongodb::error::Error) -> bool {
+    matches!(
+        *error.kind,
+        ErrorKind::Command(ref command) if SEARCH_UNAVAILABLE.contains(&command.code)
+    )
+}
+
+/// The repositories backed by MongoDB. Catalogue queries are cached in-process, see
+/// [`QueryCache`].
+#[derive(Clone)]
+pub struct MongoStore {
+    db: Database,
+    cache: Arc<QueryCache>,
+    search: Search,
+    /// Set once the server has rejected a `$search` stage, after which searches skip Atlas until
+    /// the process restarts.
+    atlas_unavailable: Arc<AtomicBool>,
+}
+
+impl MongoStore {
+    pub fn new(db: Database) -> Self {
+        MongoStore {
+            db,
+            cache: Arc::new(QueryCache::default()),
+            search: Search::Atlas,
+            atlas_unavailable: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn with_search(mut self, search: Search) -> Self {
+        self.search = search;
+        self
+    }
+
+    async fn names(&self) -> mongodb::error::Result<Arc<Vec<(i64, String)>>> {
+        if let Some(names) = self.cache.names.get("names") {
+            return Ok(names);
+        }
+        let names = Arc::new(timed("get_names", products::get_names(&self.db)).await?);
+        self.cache.names.insert("names".to_string(), names.clone());
+        Ok(names)
+    }
+
+    /// The indices of the products matching `filter`.
+    async fn matching(&self, filter: &Document) -> mongodb::error::Result<HashSet<i64>> {
+        let stages = vec![doc! { "$match": filter.clone() }];
+        let key = cache::key(&stages);
+        let query = timed("get_indices", products::get_indices(&self.db, stages));
+        let indices = cached(&self.cache.indices, key, query).await?;
+        Ok(indices.into_iter().collect())
+    }
+
+    /// Replaces the `$search` stage of `pipeline` with the products ranked by [`search::rank`].
+    /// The ranking is cut short at [`search::MAX_RESULTS`] only after the filters of the listing
+    /// have been applied, so that no match within the subdomain is lost to those outside it.
+    async fn search_locally(
+        &self,
+        text: &str,
+        pipeline: Vec<Document>,
+    ) -> mongodb::error::Result<Vec<Document>> {
+        let names = self.names().await?;
+        let mut ranked = search::rank(
+            text,
+            names.iter().map(|(index, name)| (*index, name.as_str())),
+        );
+        if let Some(filter) = search::filter_of(&pipeline) {
+            let matching = self.matching(filter).await?;
+            ranked.retain(|index| matching.contains(index));
+        }
+        ranked.truncate(search::MAX_RESULTS);
+        Ok(search::replace_search(pipeline, &ranked))
+    }
+
+    /// Runs a product pipeline. Its `$search` stage is answered in-process when so configured,
+    /// or when Atlas Search fails, as it does on deployments without the search index. Only the
+    /// errors saying so skip Atlas from then on; other failures fall back for this query alone.
+    async fn run<T, F>(
+        &self,
+        function: &'static str,
+        pipeline: Vec<Document>,
+        query: impl Fn(Vec<Document>) -> F,
+    ) -> mongodb::error::Result<T>
+    where
+        F: Future<Output = mongodb::error::Result<T>>,
+    {
+        let Some(text) = search::search_of(&pipeline) else {
+            return timed(function, query(pipeline)).await;
+        };
+        if self.search == Search::Atlas && !self.atlas_unavailable.load(Ordering::Relaxed) {
+            match timed(function, query(pipeline.clone())).await {
+                Err(error) if search_unavailable(&error) => {
+                    tracing::warn!(%error, "Atlas Search is unavailable, searching locally");
+                    self.atlas_unavailable.store(true, Ordering::Relaxed);
+                }
+                Err(error) => tracing::warn!(%error, "Atlas Search failed, searching locally"),
+                result => return result,
+            }
+        }
+        let pipeline = self.search_locally(&text, pipeline).await?;
+        timed(function, query(pipeline)).await
+    }
+}
+
+#[async_trait]
+impl ProductRepository for MongoStore {
+    async fn get_products(&self, pipeline: Vec<Document>) -> Result<Vec<Product>, AppError> {
+        let key = cache::key(&pipeline);
+        let query = self.run("get_products", pipeline, |pipeline| {
+            products::get_products(&self.db, pipeline)
+        });
+        Ok(cached(&self.cache.products, key, query).await?)
+    }
+
+    async fn get_page(
+        &self,
+        query: Vec<Document>,
+        paging: Vec<Document>,
+    ) -> Result<Page<Product>, AppError> {
+        let key = cache::key(&[query.as_slice(), paging.as_slice()].concat());
+        let query = self.run("get_page", query, |query| {
+            products::get_page(&self.db, query, paging.clone())
+        });
+        Ok(cached(&self.cache.pages, key, query).await?)
+    }
+
+    async fn get_indices(&self, pipeline: Vec<Document>) -> Result<Vec<i64>, AppError> {
+        let key = cache::key(&pipeline);
+        let query = self.run("get_indices", pipeline, |pipeline| {
+            products::get_indices(&self.db, pipeline)
+        });
+        Ok(cached(&self.cache.indices, key, query).await?)
+    }
+
+    async fn get_distinct(&self, field: &str, is_taxfree: bool) -> Vec<String> {
+        let key = format!("{field}:{is_taxfree}");
+        let query = timed(
+            "get_distinct",
+            metadata::get_distinct(&self.db, field, is_taxfree),
+        );
+        cached(&self.cache.distinct, key, query)
+            .await
+            .unwrap_or_else(|error| {
+                tracing::error!(%error, field, "Could not list distinct values");
+                vec![]
+            })
+    }
+
+    async fn get_product(&self, index: i64) -> Option<Product> {
+        timed("get_product", products::get_product(&self.db, index)).await
+    }
+}
+
+#[async_trait]
+impl UserRepository for MongoStore {
+    async fn get_user_by_name(&self, username: &str) -> Option<User> {
+        timed(
+            "get_user_by_name",
+            users::get_user_by_name(&self.db, username),
+        )
+        .await
+    }
+
+    async fn get_user_by_id(&self, user_id: &ObjectId) -> Option<User> {
+        timed("get_user_by_id", users::get_user_by_id(&self.db, user_id)).await
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> Option<User> {
+        timed(
+            "get_user_by_email",
+            users::get_user_by_email(&self.db, email),
+        )
+        .await
+    }
+
+    async fn get_user_by_list_slug(&self, slug: &str) -> Option<User> {
+        timed(
+            "get_user_by_list_slug",
+            users::get_user_by_list_slug(&self.db, slug),
+        )
+        .await
+    }
+
+    async fn get_notified_users(&self) -> Result<Vec<User>, AppError> {
+        timed("get_notified_users", users::get_notified_users(&self.db)).await
+    }
+
+    async fn create_user(&self, user: &User) -> Result<(), AppError> {
+        timed("create_user", users::create_user(&self.db, user)).await
+    }
+
+    async fn toggle_favourite(&self, user_id: &ObjectId, index: &i64) -> Result<(), AppError> {
+        timed(
+            "toggle_favourite",
+            users::toggle_favourite(&self.db, user_id, index),
+        )
+        .await
+    }
+
+    async fn notification(&self, user_id: &ObjectId, notify: bool) -> Result<(), AppError> {
+        timed(
+            "notification",
+            users::notification(&self.db, user_id, notify),
+        )
+        .await
+    }
+
+    async fn update_password(&self, user_id: &ObjectId, password: &str) -> Result<(), AppError> {
+        timed(
+            "update_password",
+            users::update_password(&self.db, user_id, password),
+        )
+        .await
+    }
+
+    async fn set_verified(&self, user_id: &ObjectId) -> Result<(), AppError> {
+        timed("set_verified", users::set_verified(&self.db, user_id)).await
+    }
+
+    async fn take_user_to_verify(&self) -> Result<Option<User>, AppError> {
+        timed("take_user_to_verify", users::take_user_to_verify(&self.db)).await
+    }
+
+    async fn update_username(&self, user_id: &ObjectId, username: &str) -> Result<(), AppError> {
+        timed(
+            "update_username",
+            users::update_username(&self.db, user_id, username),
+        )
+        .await
+    }
+
+    async fn create_list(
+        &self,
+        user_id: &ObjectId,
+        list: &FavouriteList,
+    ) -> Result<bool, AppError> {
+        timed("create_list", users::create_list(&self.db, user_id, list)).await
+    }
+
+    async fn rename_list(
+        &self,
+        user_id: &ObjectId,
+        list_id: &str,
+        name: &str,
+    ) -> Result<Option<FavouriteList>, AppError> {
+        timed(
+            "rename_list",
+            users::rename_list(&self.db, user_id, list_id, name),
+        )
+        .await
+    }
+
+    async fn delete_list(&self, user_id: &ObjectId, list_id: &str) -> Result<bool, AppError> {
+        timed(
+            "delete_list",
+            users::delete_list(&self.db, user_id, list_id),
+        )
+        .await
+    }
+
+    async fn share_list(
+        &self,
+        user_id: &ObjectId,
+        list_id: &str,
+        slug: &str,
+    ) -> Result<Option<FavouriteList>, AppError> {
+        timed(
+            "share_list",
+            users::share_list(&self.db, user_id, list_id, slug),
+        )
+        .await
+    }
+
+    async fn unshare_list(&self, user_id: &ObjectId, list_id: &str) -> Result<bool, AppError> {
+        timed(
+            "unshare_list",
+            users::unshare_list(&self.db, user_id, list_id),
+        )
+        .await
+    }
+
+    async fn put_entry(
+        &self,
+        user_id: &ObjectId,
+        list_id: &str,
+        entry: &FavouriteEntry,
+    ) -> Result<Option<FavouriteList>, AppError> {
+        timed(
+            "put_entry",
+            users::put_entry(&self.db, user_id, list_id, entry),
+        )
+        .await
+    }
+
+    async fn remove_entry(
+        &self,
+        user_id: &ObjectId,
+        list_id: &str,
+        index: i64,
+    ) -> Result<bool, AppError> {
+        timed(
+            "remove_entry",
+            users::remove_entry(&self.db, user_id, list_id, index),
+        )
+        .await
+    }
+
+    async fn move_entry(
+        &self,
+        user_id: &ObjectId,
+        from: &str,
+        to: &str,
+        entry: &FavouriteEntry,
+    ) -> Result<bool, AppError> {
+        timed(
+            "move_entry",
+            users::move_entry(&self.db, user_id, from, to, entry),
+        )
+        .await
+    }
+
+    async fn update_email(&self, user_id: &ObjectId, email: &str) -> Result<(), AppError> {
+        timed(
+            "update_email",
+            users::update_email(&self.db, user_id, email),
+        )
+        .await
+    }
+
+    async fn delete_user(&self, user_id: &ObjectId) -> Result<(), AppError> {
+        timed("delete_user", users::delete_user(&self.db, user_id)).await
+    }
+
+    async fn get_saved_searches(&self, user_id: &ObjectId) -> Result<Vec<SavedSearch>, AppError> {
+        timed(
+            "get_saved_searches",
+            users::get_saved_searches(&self.db, user_id),
+        )
+        .await
+    }
+
+    async fn get_saved_searches_for_subdomain(
+        &self,
+        subdomain: &str,
+    ) -> Result<Vec<SavedSearch>, AppError> {
+        timed(
+            "get_saved_searches_for_subdomain",
+            users::get_saved_searches_for_subdomain(&self.db, subdomain),
+        )
+        .await
+    }
+
+    async fn count_saved_searches(&self, user_id: &ObjectId) -> Result<u64, AppError> {
+        timed(
+            "count_saved_searches",
+            users::count_saved_searches(&self.db, user_id),
+        )
+        .await
+    }
+
+    async fn create_saved_search(&self, search: &SavedSearch) -> Result<(), AppError> {
+        timed(
+            "create_saved_search",
+            users::create_saved_search(&self.db, search),
+        )
+        .await
+    }
+
+    async fn update_saved_search(&self, search: &SavedSearch) -> Result<(), AppError> {
+        timed(
+            "update_saved_search",
+            users::update_saved_search(&self.db, search),
+        )
+        .await
+    }
+
+    async fn record_search_matches(
+        &self,
+        search_id: &ObjectId,
+        matches: &[i64],
+        fresh: &[i64],
+    ) -> Result<(), AppError> {
+        timed(
+            "record_search_matches",
+            users::record_search_matches(&self.db, search_id, matches, fresh),
+        )
+        .await
+    }
+
+    async fn delete_saved_search(
+        &self,
+        user_id: &ObjectId,
+        search_id: &ObjectId,
+    ) -> Result<(), AppError> {
+        timed(
+            "delete_saved_search",
+            users::delete_saved_search(&self.db, user_id, search_id),
+        )
+        .await
+    }
+
+    async fn get_alerts(&self, user_id: &ObjectId) -> Result<Vec<Alert>, AppError> {
+        timed("get_alerts", users::get_alerts(&self.db, user_id)).await
+    }
+
+    async fn get_alerts_for_fields(&self, fields: &[AlertField]) -> Result<Vec<Alert>, AppError> {
+        timed(
+            "get_alerts_for_fields",
+            users::get_alerts_for_fields(&self.db, fields),
+        )
+        .await
+    }
+
+    async fn count_alerts(&self, user_id: &ObjectId) -> Result<u64, AppError> {
+        timed("count_alerts", users::count_alerts(&self.db, user_id)).await
+    }
+
+    async fn create_alert(&self, alert: &Alert) -> Result<(), AppError> {
+        timed("create_alert", users::create_alert(&self.db, alert)).await
+    }
+
+    async fn record_alert(&self, alert: &Alert) -> Result<(), AppError> {
+        timed("record_alert", users::record_alert(&self.db, alert)).await
+    }
+
+    async fn dismiss_alerts(&self, user_id: &ObjectId) -> Result<(), AppError> {
+        timed("dismiss_alerts", users::dismiss_alerts(&self.db, user_id)).await
+    }
+
+    async fn delete_alert(&self, user_id: &ObjectId, alert_id: &ObjectId) -> Result<(), AppError> {
+        timed(
+            "delete_alert",
+            users::delete_alert(&self.db, user_id, alert_id),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl SessionRepository for MongoStore {
+    async fn store_session(&self, session: Session) -> Result<(), AppError> {
+        timed("store_session", users::store_session(&self.db, session)).await
+    }
+
+    async fn get_session(&self, session_id: &str) -> Result<Session, AppError> {
+        timed(
+            "get_user_by_session_id",
+            users::get_user_by_session_id(&self.db, session_id),
+        )
+        .await
+    }
+
+    async fn get_sessions_for_user(&self, user_id: &ObjectId) -> Result<Vec<Session>, AppError> {
+        timed(
+            "get_sessions_for_user",
+            users::get_sessions_for_user(&self.db, user_id),
+        )
+        .await
+    }
+
+    async fn update_expirations(&self, session_ids: &[String]) -> Result<(), AppError> {
+        timed(
+            "update_expirations",
+            users::update_expirations(&self.db, session_ids),
+        )
+        .await
+    }
+
+    async fn delete_session(&self, session_id: &str) -> Result<(), AppError> {
+        timed("logout", users::logout(&self.db, session_id)).await
+    }
+
+    async fn delete_sessions_for_user(&self, user_id: &ObjectId) -> Result<(), AppError> {
+        timed(
+            "delete_sessions_for_user",
+            users::delete_sessions_for_user(&self.db, user_id),
+        )
+        .await
+    }
+
+    async fn delete_session_by_id(
+        &self,
+        user_id: &ObjectId,
+        id: &ObjectId,
+    ) -> Result<(), AppError> {
+        timed(
+            "delete_session_by_id",
+            users::delete_session_by_id(&self.db, user_id, id),
+        )
+        .await
+    }
+
+    async fn delete_other_sessions(
+        &self,
+        user_id: &ObjectId,
+        session_id: &str,
+    ) -> Result<(), AppError> {
+        timed(
+            "delete_other_sessions",
+            users::delete_other_sessions(&self.db, user_id, session_id),
+        )
+        .await
+    }
+
+    async fn delete_expired_sessions(&self) -> Result<u64, AppError> {
+        timed(
+            "delete_expired_sessions",
+            users::delete_expired_sessions(&self.db),
+        )
+        .await
+    }
+
+    async fn count_active_sessions(&self) -> Result<u64, AppError> {
+        timed(
+            "count_active_sessions",
+            users::count_active_sessions(&self.db),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl TokenRepository for MongoStore {
+    async fn store_token(&self, token: Token) -> Result<(), AppError> {
+        timed("store_token", users::store_token(&self.db, token)).await
+    }
+
+    async fn consume_token(&self, kind: TokenKind, hash: &str) -> Result<Option<Token>, AppError> {
+        timed("consume_token", users::consume_token(&self.db, kind, hash)).await
+    }
+
+    async fn delete_tokens_for_user(
+        &self,
+        user_id: &ObjectId,
+        kind: TokenKind,
+    ) -> Result<(), AppError> {
+        timed(
+            "delete_tokens_for_user",
+            users::delete_tokens_for_user(&self.db, user_id, kind),
+        )
+        .await
+    }
+
+    async fn delete_expired_tokens(&self) -> Result<u64, AppError> {
+        timed(
+            "delete_expired_tokens",
+            users::delete_expired_tokens(&self.db),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl MetadataRepository for MongoStore {
+    async fn increment_visitor(&self, month: &str, subdomain: &str, fresh: bool) {
+        timed(
+            "increment_visitor",
+            metadata::increment_visitor(&self.db, month, subdomain, fresh),
+        )
+        .await
+    }
+
+    async fn get_prices_updated(&self, subdomain: &str) -> bool {
+        if let Some(updated) = self.cache.prices(subdomain) {
+            return updated;
+        }
+        let updated = timed(
+            "get_prices_updated",
+            metadata::get_prices_updated(&self.db, subdomain),
+        )
+        .await;
+        match updated {
+            Ok(updated) => {
+                self.cache.observe_prices(subdomain, updated);
+                updated
+            }
+            // Not observed, as a failed read says nothing about whether the prices changed.
+            Err(error) => {
+                tracing::error!(%error, subdomain, "Could not fetch price status");
+                false
+            }
+        }
+    }
+
+    async fn prices_flipped(&self, job: &str, subdomain: &str) -> bool {
+        let flipped = timed(
+            "prices_flipped",
+            metadata::prices_flipped(&self.db, job, subdomain),
+        )
+        .await;
+        // The jobs run for the fresh prices, so they must not be served last month's products.
+        if flipped {
+            self.cache.observe_prices(subdomain, true);
+            self.cache.invalidate();
+        }
+        flipped
+    }
+
+    async fn get_price_snapshot(&self, subdomain: &str) -> HashMap<String, f64> {
+        timed(
+            "get_price_snapshot",
+            metadata::get_price_snapshot(&self.db, subdomain),
+        )
+        .await
+    }
+
+    async fn set_price_snapshot(&self, subdomain: &str, prices: &HashMap<String, f64>) {
+        timed(
+            "set_price_snapshot",
+            metadata::set_price_snapshot(&self.db, subdomain, prices),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::{
+        bson::from_document,
+        error::{CommandError, Error},
+    };
+
+    fn command_error(code: i32) -> Error {
+        let command: CommandError =
+            from_document(doc! { "code": code, "codeName": "", "errmsg": "" }).unwrap();
+        Error::from(ErrorKind::Command(command))
+    }
+
+    #[test]
+    fn only_missing_search_support_is_remembered() {
+        assert!(search_unavailable(&command_error(40324)));
+        assert!(search_unavailable(&command_error(31082)));
+        // MaxTimeMSExpired and Interrupted happen on healthy clusters too.
+        assert!(!search_unavailable(&command_error(50)));
+        assert!(!search_unavailable(&command_error(11601)));
+    }
+}
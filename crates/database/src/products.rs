@@ -1,73 +1,179 @@
 use futures::StreamExt;
 use mongodb::{
     Collection, Database,
-    bson::{Bson, Document, doc, from_document},
+    bson::{Bson, Document, doc, from_bson, from_document},
+    error::Result,
 };
-use shared::models::{PRODUCTS_PER_PAGE, Product};
+use shared::{
+    errors::AppError,
+    models::{PRODUCTS_PER_PAGE, Page, Product, User},
+    query::Parameters,
+    state::AppState,
+    subdomain::Subdomain,
+};
+
+/// The number of pages needed for `count` products. An empty listing still has its one page.
+pub fn max_page_from_count(count: u64) -> u64 {
+    count.div_ceil(PRODUCTS_PER_PAGE as u64).max(1)
+}
 
-fn max_page_from_count(count: u64) -> u64 {
-    count / PRODUCTS_PER_PAGE as u64 + 1
+pub fn to_page(items: Vec<Product>, total: u64) -> Page<Product> {
+    Page {
+        items,
+        total,
+        pages: max_page_from_count(total),
+    }
 }
 
-pub async fn get_products(db: &Database, pipeline: Vec<Document>) -> Vec<Product> {
+pub async fn get_products(db: &Database, pipeline: Vec<Document>) -> Result<Vec<Product>> {
     let collection: Collection<Product> = db.collection("products");
 
     let mut documents: Vec<Product> = Vec::with_capacity(PRODUCTS_PER_PAGE as usize);
 
-    match collection.aggregate(pipeline).await {
-        Ok(mut cursor) => {
-            while let Some(result) = cursor.next().await {
-                if let Ok(document) = result {
-                    if let Ok(product) = from_document(document) {
-                        documents.push(product);
-                    }
-                }
+    let mut cursor = collection.aggregate(pipeline).await?;
+    while let Some(result) = cursor.next().await {
+        match from_document::<Product>(result?) {
+            Ok(product) => documents.push(product),
+            Err(error) => {
+                tracing::warn!(%error, "Skipping product that could not be deserialized")
             }
         }
-        Err(error) => {
-            eprintln!("MongoDB find error: {:?}", error);
-        }
     }
 
-    documents
+    tracing::debug!(count = documents.len(), "Listed products");
+    Ok(documents)
 }
 
-pub async fn get_preview(db: &Database, taxfree: bool) -> Option<Product> {
-    let pipeline = if taxfree {
-        vec![
-            doc! { "$match": {
-                "taxfree.stores": { "$exists": true, "$ne": Bson::Null },
-                "taxfree.valid": true,
-                "taxfree.discount": { "$lt": 0.0 },
-            }},
-            doc! { "$sort": { "taxfree.discount": 1 } },
-            doc! { "$limit": 1 },
-        ]
-    } else {
-        vec![
-            doc! { "$match": {
-                "updated": true,
-                "orderable": true,
-                "discount": { "$lt": 0.0 },
-                "price": { "$gt": 0.0 },
-                "alcohol": { "$gt": 0.0 },
-            }},
-            doc! { "$sort": { "discount": 1 } },
-            doc! { "$limit": 1 },
-        ]
+/// Fetches the page and counts the matches in one round-trip, by running both on the output of
+/// `query` in a `$facet`.
+pub async fn get_page(
+    db: &Database,
+    query: Vec<Document>,
+    paging: Vec<Document>,
+) -> Result<Page<Product>> {
+    let collection: Collection<Document> = db.collection("products");
+
+    let mut pipeline = query;
+    pipeline.push(doc! {
+        "$facet": {
+            "items": paging,
+            "total": [{ "$count": "count" }],
+        }
+    });
+
+    let mut cursor = collection.aggregate(pipeline).await?;
+    let Some(facets) = cursor.next().await.transpose()? else {
+        return Ok(to_page(vec![], 0));
     };
-    get_products(db, pipeline).await.into_iter().next()
+
+    let items: Vec<Product> = facets
+        .get_array("items")
+        .cloned()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|item| match from_bson(item) {
+            Ok(product) => Some(product),
+            Err(error) => {
+                tracing::warn!(%error, "Skipping product that could not be deserialized");
+                None
+            }
+        })
+        .collect();
+    let total = facets
+        .get_array("total")
+        .ok()
+        .and_then(|total| total.first())
+        .and_then(Bson::as_document)
+        .and_then(|total| match total.get("count") {
+            Some(Bson::Int32(count)) => Some(*count as u64),
+            Some(Bson::Int64(count)) => Some(*count as u64),
+            _ => None,
+        })
+        .unwrap_or(0);
+
+    tracing::debug!(count = items.len(), total, "Listed page of products");
+    Ok(to_page(items, total))
 }
 
-pub async fn get_max_page(db: &Database, filter: Document) -> u64 {
-    let collection: Collection<Product> = db.collection("products");
+/// The page of products requested by `parameters`. A page past the last one is moved to the last
+/// page, and `parameters` updated to match.
+pub async fn get_listing(
+    state: &AppState,
+    parameters: &mut Parameters,
+    subdomain: &Subdomain,
+    user: &Option<User>,
+    prices_updated: bool,
+) -> std::result::Result<Page<Product>, AppError> {
+    let query = parameters.to_query(subdomain, user, prices_updated);
+    let page = state
+        .products
+        .get_page(query.clone(), parameters.to_paging(subdomain))
+        .await?;
+    if !parameters.clamp_page(page.pages) {
+        return Ok(page);
+    }
+    state
+        .products
+        .get_page(query, parameters.to_paging(subdomain))
+        .await
+}
+
+pub async fn get_indices(db: &Database, mut pipeline: Vec<Document>) -> Result<Vec<i64>> {
+    let collection: Collection<Document> = db.collection("products");
+
+    pipeline.push(doc! { "$project": { "_id": 0, "index": 1 } });
 
-    match collection.count_documents(filter).await {
-        Ok(count) => max_page_from_count(count),
-        Err(_) => 1,
+    let mut indices: Vec<i64> = Vec::new();
+    let mut cursor = collection.aggregate(pipeline).await?;
+    while let Some(document) = cursor.next().await {
+        if let Some(index) = index_of(&document?) {
+            indices.push(index);
+        }
+    }
+
+    tracing::debug!(count = indices.len(), "Listed product indices");
+    Ok(indices)
+}
+
+fn index_of(document: &Document) -> Option<i64> {
+    match document.get("index") {
+        Some(Bson::Int64(index)) => Some(*index),
+        Some(Bson::Int32(index)) => Some(*index as i64),
+        _ => None,
     }
 }
 
+/// The index and name of every product, for searching without Atlas Search.
+pub async fn get_names(db: &Database) -> Result<Vec<(i64, String)>> {
+    let collection: Collection<Document> = db.collection("products");
+
+    let mut names: Vec<(i64, String)> = Vec::new();
+    let mut cursor = collection
+        .find(doc! {})
+        .projection(doc! { "_id": 0, "index": 1, "name": 1 })
+        .await?;
+    while let Some(document) = cursor.next().await {
+        let document = document?;
+        if let (Some(index), Ok(name)) = (index_of(&document), document.get_str("name")) {
+            names.push((index, name.to_string()));
+        }
+    }
+
+    tracing::debug!(count = names.len(), "Listed product names");
+    Ok(names)
+}
+
+pub async fn get_product(db: &Database, index: i64) -> Option<Product> {
+    let collection: Collection<Product> = db.collection("products");
+
+    collection
+        .find_one(doc! { "index": index })
+        .await
+        .inspect_err(|error| tracing::error!(%error, index, "Could not fetch product"))
+        .ok()
+        .flatten()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -76,8 +182,9 @@ mod tests {
     fn max_page_from_count_handles_empty_and_partial_pages() {
         assert_eq!(max_page_from_count(0), 1);
         assert_eq!(max_page_from_count(1), 1);
-        assert_eq!(max_page_from_count(PRODUCTS_PER_PAGE as u64), 2);
+        assert_eq!(max_page_from_count(PRODUCTS_PER_PAGE as u64), 1);
         assert_eq!(max_page_from_count(PRODUCTS_PER_PAGE as u64 + 1), 2);
-        assert_eq!(max_page_from_count(PRODUCTS_PER_PAGE as u64 * 2), 3);
+        assert_eq!(max_page_from_count(PRODUCTS_PER_PAGE as u64 * 2), 2);
+        assert_eq!(max_page_from_count(PRODUCTS_PER_PAGE as u64 * 2 + 1), 3);
     }
 }
@@ -0,0 +1,293 @@
+use mongodb::bson::{Bson, Document, doc};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+
+/// Weight of a query term found as-is, against 1 for a fuzzy match, as boosted in the `$search`
+/// stage built by `Parameters::to_query`.
+const EXACT_BOOST: u32 = 10;
+
+/// Results kept per search, so that the `$in` stage replacing `$search` stays small. Callers cut
+/// the ranking short once it has been filtered.
+pub const MAX_RESULTS: usize = 1000;
+
+/// Lowercases and strips diacritics, so that "Rosé" and "rose" are the same term. The Norwegian
+/// letters are folded the way they are commonly typed without them: æ as "ae", ø as "o" and å as
+/// "a".
+pub fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        match c {
+            'æ' => folded.push_str("ae"),
+            'ø' | 'ö' | 'ó' | 'ò' | 'ô' | 'õ' => folded.push('o'),
+            'å' | 'ä' | 'á' | 'à' | 'â' | 'ã' => folded.push('a'),
+            'é' | 'è' | 'ê' | 'ë' => folded.push('e'),
+            'í' | 'ì' | 'î' | 'ï' => folded.push('i'),
+            'ú' | 'ù' | 'û' | 'ü' => folded.push('u'),
+            'ç' => folded.push('c'),
+            'ñ' => folded.push('n'),
+            'ß' => folded.push_str("ss"),
+            c => folded.push(c),
+        }
+    }
+    folded
+}
+
+/// The folded words of `text`.
+pub fn tokenize(text: &str) -> Vec<String> {
+    fold(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Edits allowed for a fuzzy match. The Atlas query allows two, but short terms allow fewer, as
+/// otherwise e.g. "gin" would match most three-letter words starting with a g.
+fn max_edits(term: &str) -> usize {
+    match term.chars().count() {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => 2,
+    }
+}
+
+/// Insertions, deletions, substitutions and transpositions of adjacent characters needed to turn
+/// `a` into `b`.
+pub fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let mut rows = vec![(0..=b.len()).collect::<Vec<usize>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1)
+                .min(row[j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+/// The query text of a `$search` stage.
+pub fn query_text(search: &Document) -> Option<String> {
+    search.iter().find_map(|(key, value)| match value {
+        Bson::String(query) if key == "query" => Some(query.clone()),
+        Bson::Document(inner) => query_text(inner),
+        Bson::Array(items) => items
+            .iter()
+            .find_map(|item| item.as_document().and_then(query_text)),
+        _ => None,
+    })
+}
+
+/// The query text of the `$search` stage of `pipeline`, if it has one.
+pub fn search_of(pipeline: &[Document]) -> Option<String> {
+    pipeline
+        .iter()
+        .find_map(|stage| stage.get_document("$search").ok())
+        .and_then(query_text)
+}
+
+/// The filter applied to the results of the `$search` stage of `pipeline`.
+pub fn filter_of(pipeline: &[Document]) -> Option<&Document> {
+    pipeline
+        .iter()
+        .skip_while(|stage| !stage.contains_key("$search"))
+        .find_map(|stage| stage.get_document("$match").ok())
+}
+
+/// The indices of the products whose names match `query`, best match first.
+///
+/// Like the Atlas query, a product matches if any of the query terms does, either as-is or within
+/// a few edits that keep the first letter. Terms found as-is weigh the most, and a term that is
+/// not in any name is only expanded to the closest terms found (`maxExpansions: 1`). Ties go to
+/// the shorter name.
+pub fn rank<'a>(query: &str, products: impl IntoIterator<Item = (i64, &'a str)>) -> Vec<i64> {
+    let mut terms = tokenize(query);
+    terms.dedup();
+    if terms.is_empty() {
+        return vec![];
+    }
+
+    let products: Vec<(i64, Vec<String>)> = products
+        .into_iter()
+        .map(|(index, name)| (index, tokenize(name)))
+        .collect();
+    let vocabulary: HashSet<&str> = products
+        .iter()
+        .flat_map(|(_, words)| words.iter().map(String::as_str))
+        .collect();
+
+    let expansions: Vec<HashSet<&str>> = terms
+        .iter()
+        .map(|term| {
+            if let Some(word) = vocabulary.get(term.as_str()) {
+                return HashSet::from([*word]);
+            }
+            let first = term.chars().next();
+            let allowed = max_edits(term);
+            let mut closest = HashSet::new();
+            let mut best = allowed;
+            for word in vocabulary
+                .iter()
+                .filter(|word| word.chars().next() == first)
+            {
+                let edits = distance(term, word);
+                if edits > best {
+                    continue;
+                }
+                if edits < best {
+                    best = edits;
+                    closest.clear();
+                }
+                if edits == best {
+                    closest.insert(*word);
+                }
+            }
+            closest
+        })
+        .collect();
+
+    let mut scored: Vec<(u32, usize, i64)> = products
+        .iter()
+        .filter_map(|(index, words)| {
+            let score: u32 = terms
+                .iter()
+                .zip(&expansions)
+                .map(|(term, expansion)| {
+                    if words.contains(term) {
+                        EXACT_BOOST + 1
+                    } else if words.iter().any(|word| expansion.contains(word.as_str())) {
+                        1
+                    } else {
+                        0
+                    }
+                })
+                .sum();
+            (score > 0).then_some((score, words.len(), *index))
+        })
+        .collect();
+    scored.sort_by_key(|&(score, length, index)| (Reverse(score), length, index));
+    scored.into_iter().map(|(_, _, index)| index).collect()
+}
+
+/// Replaces the `$search` stage of `pipeline` with stages selecting the `ranked` products in
+/// their order, so that the remaining stages apply as they would to the Atlas results.
+pub fn replace_search(pipeline: Vec<Document>, ranked: &[i64]) -> Vec<Document> {
+    let ranked = ranked.to_vec();
+    let mut stages = vec![
+        doc! { "$match": { "index": { "$in": ranked.clone() } } },
+        doc! { "$addFields": { "searchRank": { "$indexOfArray": [ranked, "$index"] } } },
+        doc! { "$sort": { "searchRank": 1 } },
+    ];
+    stages.extend(
+        pipeline
+            .into_iter()
+            .filter(|stage| !stage.contains_key("$search")),
+    );
+    stages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{query::Parameters, subdomain::Subdomain};
+
+    const NAMES: &[(i64, &str)] = &[
+        (1, "Barolo Riserva 2016"),
+        (2, "Barolo"),
+        (3, "Brunello di Montalcino Riserva"),
+        (4, "Løiten Linie Aquavit"),
+        (5, "Whispering Angel Rosé"),
+        (6, "Ærøskøbing Øl"),
+        (7, "Gin & Tonic"),
+    ];
+
+    fn search(query: &str) -> Vec<i64> {
+        rank(query, NAMES.iter().map(|(index, name)| (*index, *name)))
+    }
+
+    #[test]
+    fn fold_strips_diacritics_and_norwegian_letters() {
+        assert_eq!(fold("Rosé"), "rose");
+        assert_eq!(fold("LØITEN"), "loiten");
+        assert_eq!(fold("Ærøskøbing Blåbær"), "aeroskobing blabaer");
+        assert_eq!(tokenize("Gin & Tonic, 70cl"), vec!["gin", "tonic", "70cl"]);
+    }
+
+    #[test]
+    fn distance_counts_transpositions_as_one_edit() {
+        assert_eq!(distance("barolo", "barolo"), 0);
+        assert_eq!(distance("barlo", "barolo"), 1);
+        assert_eq!(distance("baorlo", "barolo"), 1);
+        assert_eq!(distance("brunelo", "brunello"), 1);
+        assert_eq!(distance("", "gin"), 3);
+    }
+
+    #[test]
+    fn exact_terms_outrank_fuzzy_and_shorter_names_win_ties() {
+        assert_eq!(search("barolo"), vec![2, 1]);
+        // More matched terms rank higher, as any single one suffices to match.
+        assert_eq!(search("barolo riserva"), vec![1, 2, 3]);
+        assert_eq!(search("riserva"), vec![1, 3]);
+    }
+
+    #[test]
+    fn typos_and_missing_diacritics_still_match() {
+        assert_eq!(search("barlo"), vec![2, 1]);
+        assert_eq!(search("brunelo"), vec![3]);
+        assert_eq!(search("rose"), vec![5]);
+        assert_eq!(search("loiten"), vec![4]);
+        assert_eq!(search("Løiten"), vec![4]);
+        assert_eq!(search("aeroskobing"), vec![6]);
+    }
+
+    #[test]
+    fn fuzzy_matches_keep_the_first_letter_and_short_terms_exact() {
+        assert!(search("arolo").is_empty());
+        assert_eq!(search("gin"), vec![7]);
+        assert!(search("gn").is_empty());
+        assert!(search("").is_empty());
+    }
+
+    #[test]
+    fn rank_keeps_every_match_for_the_caller_to_filter() {
+        let names: Vec<(i64, String)> = (0..=MAX_RESULTS as i64)
+            .map(|index| (index, format!("Gin {index}")))
+            .collect();
+        let ranked = rank(
+            "gin",
+            names.iter().map(|(index, name)| (*index, name.as_str())),
+        );
+        assert_eq!(ranked.len(), MAX_RESULTS + 1);
+    }
+
+    #[test]
+    fn replace_search_keeps_the_stages_after_search() {
+        let parameters = Parameters {
+            search: Some("barolo".to_string()),
+            ..Default::default()
+        };
+        let pipeline = parameters.to_pipeline(&Subdomain::Vinmonopolet, &None, true);
+        assert_eq!(search_of(&pipeline).as_deref(), Some("barolo"));
+
+        let filter = filter_of(&pipeline).unwrap();
+        assert_eq!(Some(filter), pipeline[1].get_document("$match").ok());
+        assert_eq!(filter_of(&pipeline[1..]), None);
+
+        let replaced = replace_search(pipeline.clone(), &[2, 1]);
+        assert_eq!(search_of(&replaced), None);
+        assert_eq!(
+            replaced[0],
+            doc! { "$match": { "index": { "$in": [2_i64, 1_i64] } } }
+        );
+        assert_eq!(replaced[2], doc! { "$sort": { "searchRank": 1 } });
+        assert_eq!(replaced[3..], pipeline[1..]);
+    }
+}
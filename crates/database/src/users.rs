@@ -1,44 +1,93 @@
+use futures::TryStreamExt;
 use mongodb::{
     Collection, Database,
-    bson::{DateTime, doc, oid::ObjectId},
+    bson::{DateTime, doc, oid::ObjectId, to_bson},
+    error::{ErrorKind, WriteFailure},
+    options::ReturnDocument,
 };
 use std::time::{Duration, SystemTime};
 
+use crate::indexes::email_collation;
 use shared::{
     errors::AppError,
-    models::{ONE_MONTH, Session, User},
+    models::{
+        Alert, AlertField, FavouriteEntry, FavouriteList, MAX_FAVOURITE_LISTS, MAX_LIST_ENTRIES,
+        ONE_MONTH, SavedSearch, Session, Token, TokenKind, User,
+    },
 };
 
 pub async fn get_user_by_name(db: &Database, username: &str) -> Option<User> {
     let collection: Collection<User> = db.collection("users");
 
-    match collection.find_one(doc! { "username": username }).await {
-        Ok(user) => return user,
-        Err(_) => return None,
+    collection
+        .find_one(doc! { "username": username })
+        .await
+        .inspect_err(|error| tracing::error!(%error, "Could not fetch user by name"))
+        .ok()
+        .flatten()
+}
+
+pub async fn get_notified_users(db: &Database) -> Result<Vec<User>, AppError> {
+    let collection: Collection<User> = db.collection("users");
+
+    let users = collection
+        .find(doc! { "notify": true, "verified": true })
+        .await?
+        .try_collect()
+        .await?;
+    Ok(users)
+}
+
+/// Reports a write rejected by the unique index on `email` as the address being in use, which
+/// happens when two requests for the same address both pass `ensure_email_available`.
+fn email_in_use(error: mongodb::error::Error) -> AppError {
+    match *error.kind {
+        ErrorKind::Write(WriteFailure::WriteError(ref failure)) if failure.code == 11000 => {
+            AppError::BadRequest("E-postadressen er allerede i bruk.".to_string())
+        }
+        _ => AppError::MongoError(error),
     }
 }
 
 pub async fn create_user(db: &Database, user: &User) -> Result<(), AppError> {
     let collection = db.collection::<User>("users");
 
-    collection.insert_one(user).await?;
+    collection.insert_one(user).await.map_err(email_in_use)?;
     Ok(())
 }
 
 pub async fn get_user_by_id(db: &Database, user_id: &ObjectId) -> Option<User> {
     let collection: Collection<User> = db.collection("users");
 
-    match collection.find_one(doc! { "_id": user_id }).await {
-        Ok(user) => return user,
-        Err(_) => return None,
-    }
+    collection
+        .find_one(doc! { "_id": user_id })
+        .await
+        .inspect_err(|error| tracing::error!(%error, %user_id, "Could not fetch user"))
+        .ok()
+        .flatten()
 }
 
-pub async fn favourites(db: &Database, user_id: &ObjectId) -> Result<Vec<i64>, AppError> {
-    match get_user_by_id(db, user_id).await {
-        Some(user) => return Ok(user.favourites),
-        None => return Err(AppError::NotFound),
-    }
+pub async fn get_user_by_list_slug(db: &Database, slug: &str) -> Option<User> {
+    let collection: Collection<User> = db.collection("users");
+
+    collection
+        .find_one(doc! { "lists.slug": slug })
+        .await
+        .inspect_err(|error| tracing::error!(%error, "Could not fetch shared list"))
+        .ok()
+        .flatten()
+}
+
+pub async fn get_user_by_email(db: &Database, email: &str) -> Option<User> {
+    let collection: Collection<User> = db.collection("users");
+
+    collection
+        .find_one(doc! { "email": email })
+        .collation(email_collation())
+        .await
+        .inspect_err(|error| tracing::error!(%error, "Could not fetch user by e-mail"))
+        .ok()
+        .flatten()
 }
 
 pub async fn toggle_favourite(
@@ -96,6 +145,98 @@ pub async fn notification(db: &Database, user_id: &ObjectId, notify: bool) -> Re
     Ok(())
 }
 
+pub async fn update_password(
+    db: &Database,
+    user_id: &ObjectId,
+    password: &str,
+) -> Result<(), AppError> {
+    let collection = db.collection::<User>("users");
+
+    let result = collection
+        .update_one(
+            doc! { "_id": user_id },
+            doc! { "$set": { "password": password } },
+        )
+        .await?;
+
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(())
+}
+
+pub async fn update_username(
+    db: &Database,
+    user_id: &ObjectId,
+    username: &str,
+) -> Result<(), AppError> {
+    let collection = db.collection::<User>("users");
+
+    let result = collection
+        .update_one(
+            doc! { "_id": user_id },
+            doc! { "$set": { "username": username } },
+        )
+        .await?;
+
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(())
+}
+
+pub async fn update_email(db: &Database, user_id: &ObjectId, email: &str) -> Result<(), AppError> {
+    let collection = db.collection::<User>("users");
+
+    let result = collection
+        .update_one(
+            doc! { "_id": user_id },
+            doc! { "$set": { "email": email, "verified": false } },
+        )
+        .await
+        .map_err(email_in_use)?;
+
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(())
+}
+
+/// Marks one user that wants notifications but signed up before addresses were verified, i.e.
+/// lacks the `verified` field, as unverified and returns it. The update claims the user, so that
+/// it is asked to verify once even with several instances running.
+pub async fn take_user_to_verify(db: &Database) -> Result<Option<User>, AppError> {
+    let collection = db.collection::<User>("users");
+
+    let user = collection
+        .find_one_and_update(
+            doc! { "notify": true, "verified": { "$exists": false } },
+            doc! { "$set": { "verified": false } },
+        )
+        .await?;
+    Ok(user)
+}
+
+pub async fn set_verified(db: &Database, user_id: &ObjectId) -> Result<(), AppError> {
+    let collection = db.collection::<User>("users");
+
+    let result = collection
+        .update_one(
+            doc! { "_id": user_id },
+            doc! { "$set": { "verified": true } },
+        )
+        .await?;
+
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(())
+}
+
 pub async fn logout(db: &Database, session_id: &str) -> Result<(), AppError> {
     let collection = db.collection::<Session>("sessions");
 
@@ -110,29 +251,257 @@ pub async fn get_user_by_session_id(db: &Database, session_id: &str) -> Result<S
         .find_one(doc! { "session_id": session_id })
         .await?
     {
-        Some(session) => return Ok(session),
-        None => return Err(AppError::NotFound),
+        Some(session) => Ok(session),
+        None => Err(AppError::NotFound),
     }
 }
 
-pub async fn update_expiration(db: Database, session_id: String) -> Result<(), AppError> {
+pub async fn update_expirations(db: &Database, session_ids: &[String]) -> Result<(), AppError> {
     let collection = db.collection::<Session>("sessions");
 
     let expires_after =
         DateTime::from_system_time(SystemTime::now() + Duration::from_secs(ONE_MONTH));
     collection
-        .update_one(
-            doc! { "session_id": &session_id },
-            doc! { "$set": { "expiresAfter": expires_after }},
+        .update_many(
+            doc! { "session_id": { "$in": session_ids } },
+            doc! { "$set": { "expiresAfter": expires_after, "lastSeen": DateTime::now() }},
         )
         .await?;
 
     Ok(())
 }
 
+/// Returns the list `list_id` from a user returned by an update.
+fn updated_list(user: Option<User>, list_id: &str) -> Option<FavouriteList> {
+    user?.lists.into_iter().find(|list| list.id == list_id)
+}
+
+/// Adds `list`, unless the user already has `MAX_FAVOURITE_LISTS` lists.
+pub async fn create_list(
+    db: &Database,
+    user_id: &ObjectId,
+    list: &FavouriteList,
+) -> Result<bool, AppError> {
+    let collection = db.collection::<User>("users");
+
+    let list = to_bson(list).map_err(|_| AppError::InternalServerError)?;
+    let result = collection
+        .update_one(
+            doc! {
+                "_id": user_id,
+                format!("lists.{}", MAX_FAVOURITE_LISTS - 1): { "$exists": false },
+            },
+            doc! { "$push": { "lists": list } },
+        )
+        .await?;
+    Ok(result.matched_count == 1)
+}
+
+pub async fn rename_list(
+    db: &Database,
+    user_id: &ObjectId,
+    list_id: &str,
+    name: &str,
+) -> Result<Option<FavouriteList>, AppError> {
+    let collection = db.collection::<User>("users");
+
+    let user = collection
+        .find_one_and_update(
+            doc! { "_id": user_id, "lists.id": list_id },
+            doc! { "$set": { "lists.$[l].name": name } },
+        )
+        .array_filters(vec![doc! { "l.id": list_id }])
+        .return_document(ReturnDocument::After)
+        .await?;
+    Ok(updated_list(user, list_id))
+}
+
+pub async fn delete_list(
+    db: &Database,
+    user_id: &ObjectId,
+    list_id: &str,
+) -> Result<bool, AppError> {
+    let collection = db.collection::<User>("users");
+
+    let result = collection
+        .update_one(
+            doc! { "_id": user_id, "lists.id": list_id },
+            doc! { "$pull": { "lists": { "id": list_id } } },
+        )
+        .await?;
+    Ok(result.modified_count == 1)
+}
+
+/// Sets the slug of a list that has none, so that concurrent requests agree on its address.
+pub async fn share_list(
+    db: &Database,
+    user_id: &ObjectId,
+    list_id: &str,
+    slug: &str,
+) -> Result<Option<FavouriteList>, AppError> {
+    let collection = db.collection::<User>("users");
+
+    let user = collection
+        .find_one_and_update(
+            doc! { "_id": user_id, "lists.id": list_id },
+            doc! { "$set": { "lists.$[l].slug": slug } },
+        )
+        .array_filters(vec![doc! { "l.id": list_id, "l.slug": null }])
+        .return_document(ReturnDocument::After)
+        .await?;
+    Ok(updated_list(user, list_id))
+}
+
+pub async fn unshare_list(
+    db: &Database,
+    user_id: &ObjectId,
+    list_id: &str,
+) -> Result<bool, AppError> {
+    let collection = db.collection::<User>("users");
+
+    let result = collection
+        .update_one(
+            doc! { "_id": user_id, "lists.id": list_id },
+            doc! { "$unset": { "lists.$[l].slug": "" } },
+        )
+        .array_filters(vec![doc! { "l.id": list_id }])
+        .await?;
+    Ok(result.matched_count == 1)
+}
+
+/// Updates the note and target price of the entry for the same product, keeping when it was
+/// added, or adds `entry` to a list with fewer than `MAX_LIST_ENTRIES` entries.
+pub async fn put_entry(
+    db: &Database,
+    user_id: &ObjectId,
+    list_id: &str,
+    entry: &FavouriteEntry,
+) -> Result<Option<FavouriteList>, AppError> {
+    let collection = db.collection::<User>("users");
+
+    let user = collection
+        .find_one_and_update(
+            doc! {
+                "_id": user_id,
+                "lists": { "$elemMatch": { "id": list_id, "entries.index": entry.index } },
+            },
+            doc! {
+                "$set": {
+                    "lists.$[l].entries.$[e].note": entry.note.as_deref(),
+                    "lists.$[l].entries.$[e].targetPrice": entry.target_price,
+                }
+            },
+        )
+        .array_filters(vec![
+            doc! { "l.id": list_id },
+            doc! { "e.index": entry.index },
+        ])
+        .return_document(ReturnDocument::After)
+        .await?;
+    if user.is_some() {
+        return Ok(updated_list(user, list_id));
+    }
+
+    let pushed = to_bson(entry).map_err(|_| AppError::InternalServerError)?;
+    let user = collection
+        .find_one_and_update(
+            doc! {
+                "_id": user_id,
+                "lists": {
+                    "$elemMatch": {
+                        "id": list_id,
+                        "entries.index": { "$ne": entry.index },
+                        format!("entries.{}", MAX_LIST_ENTRIES - 1): { "$exists": false },
+                    }
+                },
+            },
+            doc! { "$push": { "lists.$[l].entries": pushed } },
+        )
+        .array_filters(vec![doc! { "l.id": list_id }])
+        .return_document(ReturnDocument::After)
+        .await?;
+    Ok(updated_list(user, list_id))
+}
+
+pub async fn remove_entry(
+    db: &Database,
+    user_id: &ObjectId,
+    list_id: &str,
+    index: i64,
+) -> Result<bool, AppError> {
+    let collection = db.collection::<User>("users");
+
+    let result = collection
+        .update_one(
+            doc! {
+                "_id": user_id,
+                "lists": { "$elemMatch": { "id": list_id, "entries.index": index } },
+            },
+            doc! { "$pull": { "lists.$[l].entries": { "index": index } } },
+        )
+        .array_filters(vec![doc! { "l.id": list_id }])
+        .await?;
+    Ok(result.modified_count == 1)
+}
+
+/// Moves `entry` from the list `from` to the list `to` in one update, so that it is never in both
+/// or neither. An entry for the same product in `to` is replaced.
+pub async fn move_entry(
+    db: &Database,
+    user_id: &ObjectId,
+    from: &str,
+    to: &str,
+    entry: &FavouriteEntry,
+) -> Result<bool, AppError> {
+    let collection = db.collection::<User>("users");
+
+    collection
+        .update_one(
+            doc! {
+                "_id": user_id,
+                "lists": { "$elemMatch": { "id": from, "entries.index": entry.index } },
+            },
+            doc! { "$pull": { "lists.$[to].entries": { "index": entry.index } } },
+        )
+        .array_filters(vec![doc! { "to.id": to }])
+        .await?;
+
+    let moved = to_bson(entry).map_err(|_| AppError::InternalServerError)?;
+    let result = collection
+        .update_one(
+            doc! {
+                "_id": user_id,
+                "$and": [
+                    { "lists": { "$elemMatch": { "id": from, "entries.index": entry.index } } },
+                    {
+                        "lists": {
+                            "$elemMatch": {
+                                "id": to,
+                                format!("entries.{}", MAX_LIST_ENTRIES - 1): { "$exists": false },
+                            }
+                        }
+                    },
+                ],
+            },
+            doc! {
+                "$pull": { "lists.$[from].entries": { "index": entry.index } },
+                "$push": { "lists.$[to].entries": moved },
+            },
+        )
+        .array_filters(vec![doc! { "from.id": from }, doc! { "to.id": to }])
+        .await?;
+    Ok(result.modified_count == 1)
+}
+
 pub async fn delete_user(db: &Database, user_id: &ObjectId) -> Result<(), AppError> {
-    db.collection::<Session>("sessions")
-        .delete_one(doc! { "user_id": user_id })
+    db.collection::<Token>("tokens")
+        .delete_many(doc! { "user_id": user_id })
+        .await?;
+    db.collection::<SavedSearch>("saved_searches")
+        .delete_many(doc! { "user_id": user_id })
+        .await?;
+    db.collection::<Alert>("alerts")
+        .delete_many(doc! { "user_id": user_id })
         .await?;
     db.collection::<User>("users")
         .delete_one(doc! { "_id": user_id })
@@ -146,9 +515,310 @@ pub async fn delete_sessions_for_user(db: &Database, user_id: &ObjectId) -> Resu
     Ok(())
 }
 
+pub async fn get_sessions_for_user(
+    db: &Database,
+    user_id: &ObjectId,
+) -> Result<Vec<Session>, AppError> {
+    let collection = db.collection::<Session>("sessions");
+
+    let sessions = collection
+        .find(doc! { "user_id": user_id })
+        .await?
+        .try_collect()
+        .await?;
+    Ok(sessions)
+}
+
+pub async fn delete_session_by_id(
+    db: &Database,
+    user_id: &ObjectId,
+    id: &ObjectId,
+) -> Result<(), AppError> {
+    let collection = db.collection::<Session>("sessions");
+
+    let result = collection
+        .delete_one(doc! { "_id": id, "user_id": user_id })
+        .await?;
+
+    if result.deleted_count == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(())
+}
+
+pub async fn delete_other_sessions(
+    db: &Database,
+    user_id: &ObjectId,
+    session_id: &str,
+) -> Result<(), AppError> {
+    let collection = db.collection::<Session>("sessions");
+
+    collection
+        .delete_many(doc! { "user_id": user_id, "session_id": { "$ne": session_id } })
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_expired_sessions(db: &Database) -> Result<u64, AppError> {
+    let collection = db.collection::<Session>("sessions");
+
+    let result = collection
+        .delete_many(doc! { "expiresAfter": { "$lte": DateTime::now() } })
+        .await?;
+    Ok(result.deleted_count)
+}
+
+pub async fn count_active_sessions(db: &Database) -> Result<u64, AppError> {
+    let collection = db.collection::<Session>("sessions");
+
+    let count = collection
+        .count_documents(doc! { "expiresAfter": { "$gt": DateTime::now() } })
+        .await?;
+    Ok(count)
+}
+
 pub async fn store_session(db: &Database, session: Session) -> Result<(), AppError> {
     let collection = db.collection::<Session>("sessions");
 
     collection.insert_one(session).await?;
     Ok(())
 }
+
+pub async fn store_token(db: &Database, token: Token) -> Result<(), AppError> {
+    let collection = db.collection::<Token>("tokens");
+
+    collection.insert_one(token).await?;
+    Ok(())
+}
+
+pub async fn consume_token(
+    db: &Database,
+    kind: TokenKind,
+    hash: &str,
+) -> Result<Option<Token>, AppError> {
+    let collection = db.collection::<Token>("tokens");
+
+    Ok(collection
+        .find_one_and_delete(doc! {
+            "kind": kind.name(),
+            "hash": hash,
+            "expiresAfter": { "$gt": DateTime::now() },
+        })
+        .await?)
+}
+
+pub async fn delete_tokens_for_user(
+    db: &Database,
+    user_id: &ObjectId,
+    kind: TokenKind,
+) -> Result<(), AppError> {
+    let collection = db.collection::<Token>("tokens");
+
+    collection
+        .delete_many(doc! { "user_id": user_id, "kind": kind.name() })
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_expired_tokens(db: &Database) -> Result<u64, AppError> {
+    let collection = db.collection::<Token>("tokens");
+
+    let result = collection
+        .delete_many(doc! { "expiresAfter": { "$lte": DateTime::now() } })
+        .await?;
+    Ok(result.deleted_count)
+}
+
+pub async fn get_saved_searches(
+    db: &Database,
+    user_id: &ObjectId,
+) -> Result<Vec<SavedSearch>, AppError> {
+    let collection = db.collection::<SavedSearch>("saved_searches");
+
+    let searches = collection
+        .find(doc! { "user_id": user_id })
+        .await?
+        .try_collect()
+        .await?;
+    Ok(searches)
+}
+
+pub async fn get_saved_searches_for_subdomain(
+    db: &Database,
+    subdomain: &str,
+) -> Result<Vec<SavedSearch>, AppError> {
+    let collection = db.collection::<SavedSearch>("saved_searches");
+
+    let searches = collection
+        .find(doc! { "subdomain": subdomain })
+        .await?
+        .try_collect()
+        .await?;
+    Ok(searches)
+}
+
+pub async fn count_saved_searches(db: &Database, user_id: &ObjectId) -> Result<u64, AppError> {
+    let collection = db.collection::<SavedSearch>("saved_searches");
+
+    Ok(collection
+        .count_documents(doc! { "user_id": user_id })
+        .await?)
+}
+
+pub async fn create_saved_search(db: &Database, search: &SavedSearch) -> Result<(), AppError> {
+    let collection = db.collection::<SavedSearch>("saved_searches");
+
+    collection.insert_one(search).await?;
+    Ok(())
+}
+
+pub async fn update_saved_search(db: &Database, search: &SavedSearch) -> Result<(), AppError> {
+    let collection = db.collection::<SavedSearch>("saved_searches");
+
+    let parameters = to_bson(&search.parameters).map_err(|_| AppError::InternalServerError)?;
+    let result = collection
+        .update_one(
+            doc! { "_id": search.search_id, "user_id": search.user_id },
+            doc! { "$set": {
+                "name": &search.name,
+                "parameters": parameters,
+                "matches": &search.matches,
+                "fresh": &search.fresh,
+                "checked": search.checked,
+            }},
+        )
+        .await?;
+
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(())
+}
+
+pub async fn record_search_matches(
+    db: &Database,
+    search_id: &ObjectId,
+    matches: &[i64],
+    fresh: &[i64],
+) -> Result<(), AppError> {
+    let collection = db.collection::<SavedSearch>("saved_searches");
+
+    collection
+        .update_one(
+            doc! { "_id": search_id },
+            doc! { "$set": {
+                "matches": matches,
+                "fresh": fresh,
+                "checked": DateTime::now(),
+            }},
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_saved_search(
+    db: &Database,
+    user_id: &ObjectId,
+    search_id: &ObjectId,
+) -> Result<(), AppError> {
+    let collection = db.collection::<SavedSearch>("saved_searches");
+
+    let result = collection
+        .delete_one(doc! { "_id": search_id, "user_id": user_id })
+        .await?;
+
+    if result.deleted_count == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(())
+}
+
+pub async fn get_alerts(db: &Database, user_id: &ObjectId) -> Result<Vec<Alert>, AppError> {
+    let collection = db.collection::<Alert>("alerts");
+
+    let alerts = collection
+        .find(doc! { "user_id": user_id })
+        .await?
+        .try_collect()
+        .await?;
+    Ok(alerts)
+}
+
+pub async fn get_alerts_for_fields(
+    db: &Database,
+    fields: &[AlertField],
+) -> Result<Vec<Alert>, AppError> {
+    let collection = db.collection::<Alert>("alerts");
+
+    let fields: Vec<&str> = fields.iter().map(|field| field.name()).collect();
+    let alerts = collection
+        .find(doc! { "field": { "$in": fields } })
+        .await?
+        .try_collect()
+        .await?;
+    Ok(alerts)
+}
+
+pub async fn count_alerts(db: &Database, user_id: &ObjectId) -> Result<u64, AppError> {
+    let collection = db.collection::<Alert>("alerts");
+
+    Ok(collection
+        .count_documents(doc! { "user_id": user_id })
+        .await?)
+}
+
+pub async fn create_alert(db: &Database, alert: &Alert) -> Result<(), AppError> {
+    let collection = db.collection::<Alert>("alerts");
+
+    collection.insert_one(alert).await?;
+    Ok(())
+}
+
+pub async fn record_alert(db: &Database, alert: &Alert) -> Result<(), AppError> {
+    let collection = db.collection::<Alert>("alerts");
+
+    collection
+        .update_one(
+            doc! { "_id": alert.alert_id },
+            doc! { "$set": {
+                "value": alert.value,
+                "triggered": alert.triggered,
+                "pending": alert.pending,
+            }},
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn dismiss_alerts(db: &Database, user_id: &ObjectId) -> Result<(), AppError> {
+    let collection = db.collection::<Alert>("alerts");
+
+    collection
+        .update_many(
+            doc! { "user_id": user_id, "pending": true },
+            doc! { "$set": { "pending": false } },
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_alert(
+    db: &Database,
+    user_id: &ObjectId,
+    alert_id: &ObjectId,
+) -> Result<(), AppError> {
+    let collection = db.collection::<Alert>("alerts");
+
+    let result = collection
+        .delete_one(doc! { "_id": alert_id, "user_id": user_id })
+        .await?;
+
+    if result.deleted_count == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(())
+}
@@ -5,6 +5,7 @@ function applyFilters(resetPage = true, toggleFavourites = false) {
   if (resetPage) data.set("page", "1");
   if (toggleFavourites) {
     data.set("favourites", data.get("favourites") === "true" ? "false" : "true");
+    data.delete("list");
   }
 
   const always = new Set(["ascending", "sort"]);
@@ -18,6 +19,11 @@ function applyFilters(resetPage = true, toggleFavourites = false) {
   window.location.href = "/?" + params.toString();
 }
 
+function showList(id) {
+  document.querySelector('input[name="list"]').value = id;
+  applyFilters(true, false);
+}
+
 function changePage(newPage) {
   document.querySelector('input[name="page"]').value = newPage;
   applyFilters(false, false);
@@ -52,15 +58,18 @@ toggles.forEach((toggle) => {
 document.getElementById("clearFilters").onclick = function (event) {
   event.preventDefault();
   sessionStorage.clear();
-  window.location.href =
+  let url =
     "/?fresh=false" + (document.querySelector('input[name="favourites"]').value === "true" ? "&favourites=true" : "");
+  const list = document.querySelector('input[name="list"]').value;
+  if (list) url += "&list=" + encodeURIComponent(list);
+  window.location.href = url;
 };
 
 // Count active (non-default) filters and update the badge.
 function updateFilterBadge() {
   const params = new URLSearchParams(window.location.search);
   // skip: meta params + comparator toggles (they modify price/volume/etc., not separate filters)
-  const skip = new Set(["fresh", "page", "ascending", "sort", "favourites", "cprice", "cvolume", "calcohol", "cyear"]);
+  const skip = new Set(["fresh", "page", "ascending", "sort", "favourites", "list", "cprice", "cvolume", "calcohol", "cyear"]);
   let count = 0;
   for (const key of params.keys()) {
     if (!skip.has(key)) count++;
@@ -111,34 +120,20 @@ document.getElementById("year").addEventListener("change", function () {
   applyFilters(true, false);
 });
 document.getElementById("nsearch").addEventListener("change", function () {
+  // New searches are ranked by relevance; another sort may be chosen afterwards.
+  const sort = document.getElementById("sort");
+  if (this.value) {
+    if (!sort.querySelector('option[value="relevance"]')) sort.add(new Option("Relevans", "relevance"), 0);
+    sort.value = "relevance";
+  } else if (sort.value === "relevance") {
+    sort.value = "discount";
+  }
   applyFilters(true, false);
 });
 document.getElementById("stores-search-vinmonopolet").addEventListener("change", function () {
   applyFilters(true, false);
 });
 
-// Toggle favourite.
-const mediaQuery = window.matchMedia("(min-width: 450px)");
-document.querySelectorAll(".favourite-toggle").forEach((star) => {
-  star.addEventListener("click", async function (event) {
-    event.stopPropagation();
-
-    // Send POST request to server.
-    const index = parseInt(this.dataset.index, 10);
-    await fetch("/account/favourite", {
-      method: "POST",
-      headers: {
-        "Content-Type": "application/json",
-      },
-      credentials: "include",
-      body: JSON.stringify({ index: index }),
-    });
-
-    // Toggle star.
-    this.innerText = this.innerText.trim() === "☆" ? "★" : "☆";
-  });
-});
-
 // Touch-tap feedback on product cards (mirrors hover effect for ~2s).
 if (window.matchMedia("(hover: none)").matches) {
   document.querySelectorAll(".product").forEach((card) => {
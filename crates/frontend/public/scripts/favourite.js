@@ -0,0 +1,21 @@
+// Toggle favourite.
+const mediaQuery = window.matchMedia("(min-width: 450px)");
+document.querySelectorAll(".favourite-toggle").forEach((star) => {
+  star.addEventListener("click", async function (event) {
+    event.stopPropagation();
+
+    // Send POST request to server.
+    const index = parseInt(this.dataset.index, 10);
+    await fetch("/account/favourite", {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json",
+      },
+      credentials: "include",
+      body: JSON.stringify({ index: index }),
+    });
+
+    // Toggle star.
+    this.innerText = this.innerText.trim() === "☆" ? "★" : "☆";
+  });
+});
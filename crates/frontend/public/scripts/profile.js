@@ -1,4 +1,14 @@
-const _MODALS = ["profile", "loginForm", "registerForm", "notifyUserForm", "deleteUserForm"];
+const _MODALS = [
+  "profile",
+  "loginForm",
+  "forgotForm",
+  "resetForm",
+  "registerForm",
+  "notifyUserForm",
+  "sessionsView",
+  "accountForm",
+  "deleteUserForm",
+];
 
 function toggleView(modal) {
   // Close all modals except the one that was clicked.
@@ -12,14 +22,22 @@ function toggleView(modal) {
 
   // Open the clicked modal.
   document.getElementById(modal).classList.toggle("is-hidden");
+
+  if (modal === "sessionsView") {
+    loadSessions();
+  }
 }
 
-async function showError(message) {
+async function showMessage(message) {
   const userMessage = document.getElementById("userMessage");
   userMessage.classList.remove("is-hidden");
   userMessage.textContent = message;
 }
 
+async function showError(message) {
+  await showMessage(message);
+}
+
 async function tryPost(endpoint, formData) {
   try {
     const response = await fetch(endpoint, {
@@ -48,6 +66,55 @@ document.getElementById("loginForm").onsubmit = async function (event) {
   await tryPost("/account/login", formData);
 };
 
+document.getElementById("forgotForm").onsubmit = async function (event) {
+  event.preventDefault();
+  const formData = {
+    email: document.getElementById("emailForgot").value,
+  };
+  try {
+    const response = await fetch("/account/forgot", {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify(formData),
+    });
+    if (!response.ok) {
+      throw new Error();
+    }
+    toggleView("forgotForm");
+    showMessage("Hvis adressen er registrert har vi sendt deg en lenke.");
+  } catch (error) {
+    showError(`Hmm, noe gikk galt... ${error.message || error}`);
+  }
+};
+
+document.getElementById("resetForm").onsubmit = async function (event) {
+  event.preventDefault();
+  const formData = {
+    token: new URLSearchParams(window.location.search).get("reset"),
+    password: document.getElementById("passwordReset").value,
+  };
+  try {
+    const response = await fetch("/account/reset", {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify(formData),
+    });
+    if (!response.ok) {
+      const body = await response.json().catch(() => ({}));
+      throw new Error(body.error || "");
+    }
+    window.history.replaceState({}, "", window.location.pathname);
+    toggleView("loginForm");
+    showMessage("Passordet er endret. Logg inn med det nye passordet.");
+  } catch (error) {
+    showError(`Hmm, noe gikk galt... ${error.message || error}`);
+  }
+};
+
 document.getElementById("registerForm").onsubmit = async function (event) {
   event.preventDefault();
   const formData = {
@@ -68,6 +135,33 @@ document.getElementById("notifyUserForm").onsubmit = async function (event) {
   await tryPost("/account/notification", formData);
 };
 
+document.getElementById("usernameForm").onsubmit = async function (event) {
+  event.preventDefault();
+  const formData = {
+    username: document.getElementById("usernameChange").value,
+    password: document.getElementById("passwordUsername").value,
+  };
+  await tryPost("/account/username", formData);
+};
+
+document.getElementById("emailForm").onsubmit = async function (event) {
+  event.preventDefault();
+  const formData = {
+    email: document.getElementById("emailChange").value,
+    password: document.getElementById("passwordEmail").value,
+  };
+  await tryPost("/account/email", formData);
+};
+
+document.getElementById("passwordForm").onsubmit = async function (event) {
+  event.preventDefault();
+  const formData = {
+    password: document.getElementById("passwordCurrent").value,
+    new_password: document.getElementById("passwordNew").value,
+  };
+  await tryPost("/account/password", formData);
+};
+
 document.getElementById("deleteUserForm").onsubmit = async function (event) {
   event.preventDefault();
   const formData = {
@@ -81,8 +175,129 @@ async function logout() {
   await tryPost("/account/logout", {});
 }
 
+async function resendVerification() {
+  try {
+    const response = await fetch("/account/verify", {
+      method: "POST",
+      credentials: "include",
+    });
+    if (!response.ok) {
+      throw new Error();
+    }
+    showMessage("Vi har sendt deg en ny lenke.");
+  } catch (error) {
+    showError(`Hmm, noe gikk galt... ${error.message || error}`);
+  }
+}
+
+async function loadSessions() {
+  const list = document.getElementById("sessionsList");
+  try {
+    const response = await fetch("/account/sessions", { credentials: "include" });
+    if (!response.ok) {
+      throw new Error();
+    }
+    const sessions = await response.json();
+
+    list.replaceChildren(
+      ...sessions.map((session) => {
+        const row = document.createElement("div");
+        row.className = "adv-field";
+
+        const label = document.createElement("span");
+        const seen = session.last_seen ? new Date(session.last_seen).toLocaleString("nb-NO") : "ukjent";
+        label.textContent = `${session.user_agent || "Ukjent enhet"} (${session.ip || "ukjent IP"}), sist brukt ${seen}`;
+        row.appendChild(label);
+
+        if (session.current) {
+          const current = document.createElement("em");
+          current.textContent = "Denne enheten";
+          row.appendChild(current);
+        } else {
+          const revoke = document.createElement("button");
+          revoke.type = "button";
+          revoke.className = "btn";
+          revoke.textContent = "Logg ut";
+          revoke.onclick = () => revokeSession(session.id);
+          row.appendChild(revoke);
+        }
+        return row;
+      }),
+    );
+  } catch (error) {
+    showError(`Hmm, noe gikk galt... ${error.message || error}`);
+  }
+}
+
+async function revokeSession(id) {
+  try {
+    const response = await fetch(`/account/sessions/${id}`, {
+      method: "DELETE",
+      credentials: "include",
+    });
+    if (!response.ok) {
+      throw new Error();
+    }
+    await loadSessions();
+  } catch (error) {
+    showError(`Hmm, noe gikk galt... ${error.message || error}`);
+  }
+}
+
+async function revokeOtherSessions() {
+  try {
+    const response = await fetch("/account/sessions", {
+      method: "DELETE",
+      credentials: "include",
+    });
+    if (!response.ok) {
+      throw new Error();
+    }
+    await loadSessions();
+  } catch (error) {
+    showError(`Hmm, noe gikk galt... ${error.message || error}`);
+  }
+}
+
 window.toggleView = toggleView;
+window.revokeOtherSessions = revokeOtherSessions;
 window.logout = logout;
+window.resendVerification = resendVerification;
+window.shareList = shareList;
+window.unshareList = unshareList;
+
+async function shareList(id) {
+  try {
+    const response = await fetch(`/account/lists/${id}/share`, {
+      method: "POST",
+      credentials: "include",
+    });
+    if (!response.ok) {
+      throw new Error();
+    }
+    const list = await response.json();
+    const url = `${window.location.origin}/liste/${list.slug}`;
+    await navigator.clipboard?.writeText(url).catch(() => {});
+    showMessage(`Lenken er kopiert: ${url}`);
+  } catch (error) {
+    showError(`Hmm, noe gikk galt... ${error.message || error}`);
+  }
+}
+
+async function unshareList(id) {
+  try {
+    const response = await fetch(`/account/lists/${id}/share`, {
+      method: "DELETE",
+      credentials: "include",
+    });
+    if (!response.ok) {
+      throw new Error();
+    }
+    window.location.reload();
+  } catch (error) {
+    showError(`Hmm, noe gikk galt... ${error.message || error}`);
+  }
+}
 
 async function loadFavourites() {
   try {
@@ -96,5 +311,17 @@ async function loadFavourites() {
 }
 
 document.addEventListener("DOMContentLoaded", async () => {
+  const search = new URLSearchParams(window.location.search);
+  if (search.has("reset")) {
+    toggleView("resetForm");
+  }
+  if (search.has("verified")) {
+    showMessage(
+      search.get("verified") === "true"
+        ? "E-postadressen er bekreftet."
+        : "Lenken er ugyldig eller utløpt.",
+    );
+    window.history.replaceState({}, "", window.location.pathname);
+  }
   await loadFavourites();
 });
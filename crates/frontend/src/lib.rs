@@ -12,5 +12,7 @@ struct Assets;
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/", get(render::site))
+        .route("/produkt/{index}", get(render::product))
+        .route("/liste/{slug}", get(render::shared))
         .nest_service("/public", ServeEmbed::<Assets>::new())
 }
@@ -1,6 +1,6 @@
 use axum::{
-    extract::{Query, State},
-    http::HeaderMap,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
     response::Html,
 };
 use minijinja::{Environment, Value, context};
@@ -44,6 +44,10 @@ fn get_env() -> &'static Environment<'static> {
             iter.filter(|s| s.as_str().map(|s| re.is_match(s)).unwrap_or(false))
                 .collect::<Vec<_>>()
         });
+        env.add_filter("sparkline", |prices: Vec<f64>| {
+            Value::from_safe_string(sparkline(&prices))
+        });
+        env.add_filter("thousands", thousands);
         env.add_filter("truncate", |value: String, max: u32| -> String {
             let max = max as usize;
             let chars: Vec<char> = value.chars().collect();
@@ -57,17 +61,68 @@ fn get_env() -> &'static Environment<'static> {
     })
 }
 
+/// Groups the digits in threes, as in "1 234".
+fn thousands(value: u64) -> String {
+    let digits = value.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(' ');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+const SPARKLINE_WIDTH: f64 = 120.0;
+const SPARKLINE_HEIGHT: f64 = 24.0;
+
+/// Draws the priced months of `prices`, leaving out those without a price.
+fn sparkline(prices: &[f64]) -> String {
+    let prices: Vec<f64> = prices
+        .iter()
+        .copied()
+        .filter(|price| *price > 0.0)
+        .collect();
+    if prices.len() < 2 {
+        return String::new();
+    }
+    let min = prices.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = prices.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = if max > min { max - min } else { 1.0 };
+    let step = SPARKLINE_WIDTH / (prices.len() - 1) as f64;
+
+    let points = prices
+        .iter()
+        .enumerate()
+        .map(|(i, price)| {
+            let x = i as f64 * step;
+            let y = SPARKLINE_HEIGHT - 1.0 - (price - min) / range * (SPARKLINE_HEIGHT - 2.0);
+            format!("{:.1},{:.1}", x, y)
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    format!(
+        r#"<svg class="sparkline" viewBox="0 0 {w} {h}" width="{w}" height="{h}" preserveAspectRatio="none" aria-hidden="true"><polyline points="{points}" fill="none" stroke="currentColor" stroke-width="1.5" stroke-linejoin="round" stroke-linecap="round" /></svg>"#,
+        w = SPARKLINE_WIDTH,
+        h = SPARKLINE_HEIGHT,
+    )
+}
+
 pub fn render_landing(user: Option<User>) -> String {
     let tmpl = get_env().get_template("landing.html").unwrap();
     tmpl.render(context! { user }).unwrap()
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn render_products(
     data: &Vec<Product>,
     is_taxfree: bool,
     user: Option<User>,
     page: i64,
     max_page: u64,
+    total: u64,
     parameters: &Parameters,
     landing_url: &str,
     prices_updated: bool,
@@ -79,6 +134,7 @@ pub fn render_products(
         user,
         page,
         max_page,
+        total,
         parameters,
         landing => false,
         landing_url,
@@ -87,6 +143,46 @@ pub fn render_products(
     .unwrap()
 }
 
+/// A published favourite list, rendered read-only with the listing template.
+pub fn render_shared(
+    data: &Vec<Product>,
+    is_taxfree: bool,
+    user: Option<User>,
+    name: &str,
+    landing_url: &str,
+) -> String {
+    let tmpl = get_env().get_template("products.html").unwrap();
+    tmpl.render(context! {
+        data,
+        is_taxfree,
+        user,
+        page => 1,
+        max_page => 1,
+        parameters => Parameters::default(),
+        landing => false,
+        landing_url,
+        prices_updated => true,
+        shared => name,
+    })
+    .unwrap()
+}
+
+pub fn render_product(
+    item: &Product,
+    is_taxfree: bool,
+    user: Option<User>,
+    landing_url: &str,
+) -> String {
+    let tmpl = get_env().get_template("product.html").unwrap();
+    tmpl.render(context! {
+        item,
+        is_taxfree,
+        user,
+        landing_url,
+    })
+    .unwrap()
+}
+
 pub fn render_error(message: &str, landing_url: &str) -> String {
     let tmpl = get_env().get_template("error.html").unwrap();
     tmpl.render(context! {
@@ -96,6 +192,173 @@ pub fn render_error(message: &str, landing_url: &str) -> String {
     .unwrap()
 }
 
+pub async fn site(
+    State(state): State<AppState>,
+    subdomain: Subdomain,
+    headers: HeaderMap,
+    Query(mut parameters): Query<Parameters>,
+    MaybeAuthenticate(user): MaybeAuthenticate,
+) -> (StatusCode, Html<String>) {
+    let host = headers
+        .get("host")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("snublejuice.no");
+    let landing_url = landing_url_from_host(host);
+    if state.config.is_production() {
+        let month = chrono::Local::now().format("%Y-%m").to_string();
+        state
+            .metadata
+            .increment_visitor(&month, subdomain.name(), parameters.is_empty())
+            .await;
+    }
+
+    match subdomain {
+        Subdomain::Landing => (StatusCode::OK, Html(render_landing(user))),
+        Subdomain::Vinmonopolet | Subdomain::Taxfree => {
+            let prices_updated = state.metadata.get_prices_updated(subdomain.name()).await;
+            let page = match database::products::get_listing(
+                &state,
+                &mut parameters,
+                &subdomain,
+                &user,
+                prices_updated,
+            )
+            .await
+            {
+                Ok(page) => page,
+                Err(error) => {
+                    tracing::error!(%error, "Could not list products");
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Html(render_error("Kunne ikke hente produktene.", &landing_url)),
+                    );
+                }
+            };
+            let html = Html(render_products(
+                &page.items,
+                subdomain.is_taxfree(),
+                user,
+                parameters.page(),
+                page.pages,
+                page.total,
+                &parameters,
+                &landing_url,
+                prices_updated,
+            ));
+            (StatusCode::OK, html)
+        }
+    }
+}
+
+/// Product indices are plain digits, as in the API, so that e.g. `-1` and `+5` are not found.
+fn parse_index(index: &str) -> Option<i64> {
+    if index.is_empty() || !index.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    index.parse().ok()
+}
+
+pub async fn product(
+    State(state): State<AppState>,
+    subdomain: Subdomain,
+    headers: HeaderMap,
+    Path(index): Path<String>,
+    MaybeAuthenticate(user): MaybeAuthenticate,
+) -> (StatusCode, Html<String>) {
+    let host = headers
+        .get("host")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("snublejuice.no");
+    let landing_url = landing_url_from_host(host);
+
+    let product = match (&subdomain, parse_index(&index)) {
+        (Subdomain::Vinmonopolet | Subdomain::Taxfree, Some(index)) => {
+            state.products.get_product(index).await
+        }
+        _ => None,
+    };
+
+    match product {
+        Some(product) => (
+            StatusCode::OK,
+            Html(render_product(
+                &product,
+                subdomain.is_taxfree(),
+                user,
+                &landing_url,
+            )),
+        ),
+        None => (
+            StatusCode::NOT_FOUND,
+            Html(render_error("Fant ikke produktet.", &landing_url)),
+        ),
+    }
+}
+
+pub async fn shared(
+    State(state): State<AppState>,
+    subdomain: Subdomain,
+    headers: HeaderMap,
+    Path(slug): Path<String>,
+    MaybeAuthenticate(user): MaybeAuthenticate,
+) -> (StatusCode, Html<String>) {
+    let host = headers
+        .get("host")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("snublejuice.no");
+    let landing_url = landing_url_from_host(host);
+
+    let list = match subdomain {
+        Subdomain::Vinmonopolet | Subdomain::Taxfree => state
+            .users
+            .get_user_by_list_slug(&slug)
+            .await
+            .and_then(|owner| {
+                owner
+                    .lists
+                    .into_iter()
+                    .find(|list| list.slug.as_deref() == Some(slug.as_str()))
+            }),
+        Subdomain::Landing => None,
+    };
+    let Some(list) = list else {
+        return (
+            StatusCode::NOT_FOUND,
+            Html(render_error("Fant ikke listen.", &landing_url)),
+        );
+    };
+
+    // Keep the order of the list, and only what can be bought on this subdomain.
+    let indices = list.indices();
+    let mut products = match state.products.get_products_by_indices(&indices).await {
+        Ok(products) => products,
+        Err(error) => {
+            tracing::error!(%error, "Could not fetch the products of a shared list");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Html(render_error("Kunne ikke hente produktene.", &landing_url)),
+            );
+        }
+    };
+    products.retain(|product| !subdomain.is_taxfree() || product.taxfree.is_some());
+    products.sort_by_key(|product| {
+        indices
+            .iter()
+            .position(|&index| index == product.index as i64)
+    });
+
+    (
+        StatusCode::OK,
+        Html(render_shared(
+            &products,
+            subdomain.is_taxfree(),
+            user,
+            &list.name,
+            &landing_url,
+        )),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -148,6 +411,7 @@ mod tests {
             sort: None,
             ascending: None,
             favourites: None,
+            list: None,
             category: None,
             country: None,
             price: None,
@@ -183,11 +447,13 @@ mod tests {
             false,
             None,
             1,
-            1,
+            83,
+            1234,
             &parameters,
             "https://snublejuice.no",
             true,
         );
+        assert!(products.contains("1 av 83 · 1 234 treff"));
         assert!(products.contains(r#"href="/public/stylesheet.css""#));
         assert!(products.contains("/public/scripts/stores.js"));
         assert!(products.contains("/public/scripts/buttons.js"));
@@ -201,6 +467,23 @@ mod tests {
         assert!(error.contains("error-logo"));
     }
 
+    #[test]
+    fn parse_index_only_accepts_digits() {
+        assert_eq!(parse_index("123"), Some(123));
+        assert_eq!(parse_index("007"), Some(7));
+        for index in ["", "-1", "+5", "1e3", " 1", "99999999999999999999"] {
+            assert_eq!(parse_index(index), None);
+        }
+    }
+
+    #[test]
+    fn thousands_groups_digits() {
+        assert_eq!(thousands(0), "0");
+        assert_eq!(thousands(999), "999");
+        assert_eq!(thousands(1234), "1 234");
+        assert_eq!(thousands(1234567), "1 234 567");
+    }
+
     #[test]
     fn price_block_renders_vin_and_taxfree() {
         let parameters = empty_parameters();
@@ -210,6 +493,7 @@ mod tests {
             None,
             1,
             1,
+            0,
             &parameters,
             "https://snublejuice.no",
             true,
@@ -226,6 +510,7 @@ mod tests {
             None,
             1,
             1,
+            0,
             &parameters,
             "https://snublejuice.no",
             true,
@@ -236,60 +521,71 @@ mod tests {
         assert!(tax.contains("pcval-change"));
         assert!(tax.contains("example.com"));
     }
-}
 
-pub async fn site(
-    State(state): State<AppState>,
-    subdomain: Subdomain,
-    headers: HeaderMap,
-    Query(parameters): Query<Parameters>,
-    MaybeAuthenticate(user): MaybeAuthenticate,
-) -> Html<String> {
-    let host = headers
-        .get("host")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("snublejuice.no");
-    let landing_url = landing_url_from_host(host);
-    let is_production = std::env::var("ENVIRONMENT")
-        .map(|e| e == "production")
-        .unwrap_or(false);
+    #[test]
+    fn product_page_renders_expanded_details() {
+        let mut product = sample_product();
+        product.index = 12345;
+        product.smell = Some("Bringebær".to_string());
 
-    if is_production {
-        let month = chrono::Local::now().format("%Y-%m").to_string();
-        database::metadata::increment_visitor(
-            &state.db,
-            &month,
-            subdomain.name(),
-            parameters.is_empty(),
-        )
-        .await;
+        let page = render_product(&product, false, None, "https://snublejuice.no");
+        assert!(page.contains("<title>Snublejuice</title>"));
+        assert!(page.contains("Testvin | Snublejuice.no"));
+        assert!(page.contains("Bringebær"));
+        assert!(page.contains("/public/scripts/favourite.js"));
+        assert!(!page.contains("/public/scripts/buttons.js"));
+        assert!(!page.contains(r#"<aside class="is-hidden">"#));
+        assert!(!page.contains("/produkt/12345"));
+
+        let listing = render_products(
+            &vec![product],
+            false,
+            None,
+            1,
+            1,
+            0,
+            &empty_parameters(),
+            "https://snublejuice.no",
+            true,
+        );
+        assert!(listing.contains(r#"<aside class="is-hidden">"#));
+        assert!(listing.contains("/produkt/12345"));
     }
 
-    match subdomain {
-        Subdomain::Landing => Html(render_landing(user)),
-        Subdomain::Vinmonopolet | Subdomain::Taxfree => {
-            let prices_updated =
-                database::metadata::get_prices_updated(&state.db, subdomain.name()).await;
-            let products = database::products::get_products(
-                &state.db,
-                parameters.to_pipeline(&subdomain, &user, prices_updated),
-            )
-            .await;
-            let max_page = database::products::get_max_page(
-                &state.db,
-                parameters.to_filter(&subdomain, &user, prices_updated),
-            )
-            .await;
-            Html(render_products(
-                &products,
-                subdomain.is_taxfree(),
-                user,
-                parameters.page.unwrap_or(1),
-                max_page,
-                &parameters,
-                &landing_url,
-                prices_updated,
-            ))
-        }
+    #[test]
+    fn shared_list_renders_read_only() {
+        let page = render_shared(
+            &vec![sample_product()],
+            false,
+            None,
+            "Taxfree-tur",
+            "https://snublejuice.no",
+        );
+        assert!(page.contains("Delt liste: <b>Taxfree-tur</b>"));
+        assert!(page.contains("Testvin"));
+        assert!(!page.contains(r#"id="filter""#));
+        assert!(!page.contains("/public/scripts/buttons.js"));
+        assert!(!page.contains("favourite-toggle"));
+        assert!(!page.contains("prices-updating-banner"));
+    }
+
+    #[test]
+    fn sparkline_scales_prices_into_viewbox() {
+        assert_eq!(sparkline(&[]), "");
+        assert_eq!(sparkline(&[100.0]), "");
+
+        let svg = sparkline(&[100.0, 200.0, 150.0]);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(r#"points="0.0,23.0 60.0,1.0 120.0,12.0""#));
+
+        let flat = sparkline(&[100.0, 100.0]);
+        assert!(flat.contains(r#"points="0.0,23.0 120.0,23.0""#));
+        assert_eq!(sparkline(&[100.0, 0.0, 200.0, 150.0]), svg);
+        assert_eq!(sparkline(&[0.0, 100.0]), "");
+
+        let mut product = sample_product();
+        product.prices = vec![200.0, 180.0, 150.0];
+        let page = render_product(&product, false, None, "https://snublejuice.no");
+        assert!(page.contains(r#"<svg class="sparkline""#));
     }
 }
@@ -0,0 +1,232 @@
+use minijinja::{Environment, Value, context};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use shared::{
+    errors::AppError,
+    mail::Mail,
+    models::{Product, User},
+    subdomain::{Subdomain, subdomain_url},
+};
+
+static ENV: OnceLock<Environment<'static>> = OnceLock::new();
+
+fn get_env() -> &'static Environment<'static> {
+    ENV.get_or_init(|| {
+        let mut env = Environment::new();
+        env.add_template("digest.html", include_str!("../templates/digest.html"))
+            .unwrap();
+        env
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct PriceDrop {
+    pub index: usize,
+    pub name: String,
+    pub before: f64,
+    pub now: f64,
+    pub change: f64,
+}
+
+impl PriceDrop {
+    fn new(product: &Product, before: f64, now: f64) -> Option<Self> {
+        (before > 0.0 && now > 0.0 && now < before).then(|| PriceDrop {
+            index: product.index,
+            name: product.name.clone(),
+            before,
+            now,
+            change: 100.0 * (now - before) / before,
+        })
+    }
+}
+
+fn sorted(mut drops: Vec<PriceDrop>) -> Vec<PriceDrop> {
+    drops.sort_by(|a, b| {
+        a.change
+            .partial_cmp(&b.change)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    drops
+}
+
+/// Vinmonopolet products whose price fell in the latest monthly update.
+pub fn vinmonopolet_drops(products: &[&Product]) -> Vec<PriceDrop> {
+    sorted(
+        products
+            .iter()
+            .filter_map(|product| PriceDrop::new(product, product.oldprice?, product.price))
+            .collect(),
+    )
+}
+
+/// Tax-free products whose price fell since the `previous` snapshot (keyed by index).
+pub fn taxfree_drops(products: &[&Product], previous: &HashMap<String, f64>) -> Vec<PriceDrop> {
+    sorted(
+        products
+            .iter()
+            .filter_map(|product| {
+                let before = *previous.get(&product.index.to_string())?;
+                PriceDrop::new(product, before, product.taxfree.as_ref()?.price)
+            })
+            .collect(),
+    )
+}
+
+/// Products are linked on `subdomain` under `public_url`, so that each instance links to itself.
+pub fn render_digest(
+    user: &User,
+    subdomain: &Subdomain,
+    drops: &[PriceDrop],
+    public_url: &str,
+) -> Result<Mail, AppError> {
+    let tmpl = get_env()
+        .get_template("digest.html")
+        .map_err(|_| AppError::InternalServerError)?;
+    let body = tmpl
+        .render(context! {
+            username => user.username,
+            taxfree => subdomain.is_taxfree(),
+            base_url => Value::from_safe_string(subdomain_url(public_url, subdomain)),
+            drops,
+        })
+        .map_err(|error| AppError::MailError(error.to_string()))?;
+
+    Ok(Mail {
+        to: user.email.clone(),
+        subject: "Prisfall på favorittene dine".to_string(),
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::oid::ObjectId;
+    use shared::models::Taxfree;
+
+    fn product(index: usize, price: f64, oldprice: Option<f64>, taxfree: Option<f64>) -> Product {
+        Product {
+            index,
+            name: format!("Produkt {}", index),
+            price,
+            prices: vec![],
+            discount: 0.0,
+            volume: 75.0,
+            alcohol: 13.0,
+            literprice: 0.0,
+            url: String::new(),
+            stores: vec![],
+            category: "Rødvin".to_string(),
+            subcategory: None,
+            country: "Italia".to_string(),
+            district: None,
+            subdistrict: None,
+            description: None,
+            storage: None,
+            smell: None,
+            taste: None,
+            pair: None,
+            year: None,
+            oldprice,
+            colour: None,
+            sugar: None,
+            acid: None,
+            characteristics: vec![],
+            ingredients: vec![],
+            updated: Some(true),
+            aperitif: None,
+            taxfree: taxfree.map(|price| Taxfree {
+                url: String::new(),
+                price,
+                discount: 0.0,
+                stores: vec![],
+            }),
+        }
+    }
+
+    #[test]
+    fn vinmonopolet_drops_compares_with_oldprice() {
+        let (a, b, c) = (
+            product(1, 90.0, Some(100.0), None),
+            product(2, 150.0, Some(100.0), None),
+            product(3, 50.0, Some(100.0), None),
+        );
+        let drops = vinmonopolet_drops(&[&a, &b, &c, &product(4, 80.0, None, None)]);
+        assert_eq!(drops.len(), 2);
+        assert_eq!(drops[0].index, 3);
+        assert!((drops[0].change + 50.0).abs() < 1e-9);
+        assert_eq!(drops[1].index, 1);
+    }
+
+    #[test]
+    fn taxfree_drops_compares_with_snapshot() {
+        let previous = HashMap::from([("1".to_string(), 300.0), ("2".to_string(), 200.0)]);
+        let (a, b, c) = (
+            product(1, 400.0, None, Some(250.0)),
+            product(2, 400.0, None, Some(220.0)),
+            product(3, 400.0, None, Some(100.0)),
+        );
+        let drops = taxfree_drops(&[&a, &b, &c], &previous);
+        assert_eq!(drops.len(), 1);
+        assert_eq!(drops[0].index, 1);
+        assert!((drops[0].before - 300.0).abs() < 1e-9);
+    }
+
+    fn user() -> User {
+        User {
+            user_id: ObjectId::new(),
+            username: "snubler".to_string(),
+            password: String::new(),
+            email: "snubler@example.com".to_string(),
+            favourites: vec![],
+            notify: true,
+            verified: true,
+            lists: vec![],
+        }
+    }
+
+    #[test]
+    fn render_digest_links_products_and_escapes_names() {
+        let user = user();
+        let mut item = product(42, 90.0, Some(100.0), None);
+        item.name = "Barolo <2019>".to_string();
+        let drops = vinmonopolet_drops(&[&item]);
+
+        let mail = render_digest(
+            &user,
+            &Subdomain::Vinmonopolet,
+            &drops,
+            "https://snublejuice.no",
+        )
+        .unwrap();
+        assert_eq!(mail.to, "snubler@example.com");
+        assert!(mail.body.contains("Hei snubler"));
+        assert!(
+            mail.body
+                .contains("https://vinmonopolet.snublejuice.no/produkt/42")
+        );
+        assert!(mail.body.contains("Barolo &lt;2019&gt;"));
+        assert!(mail.body.contains("-10%"));
+    }
+
+    #[test]
+    fn render_digest_links_to_the_configured_instance() {
+        let item = product(7, 200.0, None, Some(150.0));
+        let drops = taxfree_drops(&[&item], &HashMap::from([("7".to_string(), 180.0)]));
+
+        let mail = render_digest(
+            &user(),
+            &Subdomain::Taxfree,
+            &drops,
+            "https://staging.snublejuice.no/",
+        )
+        .unwrap();
+        assert!(
+            mail.body
+                .contains("https://taxfree.staging.snublejuice.no/produkt/7")
+        );
+        assert!(!mail.body.contains("https://taxfree.snublejuice.no"));
+    }
+}
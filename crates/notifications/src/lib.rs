@@ -0,0 +1,196 @@
+pub mod digest;
+pub mod mail;
+
+use mongodb::bson::doc;
+use std::collections::HashMap;
+
+use database::jobs;
+use shared::{models::Product, state::AppState, subdomain::Subdomain};
+
+/// Sends the monthly price-drop digests once the prices of a subdomain have been updated.
+pub async fn watch(state: AppState) {
+    let mut interval = tokio::time::interval(jobs::POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        for subdomain in jobs::SUBDOMAINS {
+            if state
+                .metadata
+                .prices_flipped("notifications", subdomain)
+                .await
+            {
+                send_digests(&state, &Subdomain::from_name(subdomain)).await;
+            }
+        }
+    }
+}
+
+/// Mails each notified user the price drops among their favourites. Tax-free drops are measured
+/// against the prices recorded at the previous run, which covers the whole tax-free catalogue so
+/// that favourites added since then have a price to compare with. The first run only records the
+/// prices.
+pub async fn send_digests(state: &AppState, subdomain: &Subdomain) {
+    let recipients = match state.users.get_notified_users().await {
+        Ok(recipients) => recipients,
+        Err(error) => {
+            tracing::error!(%error, "Could not fetch users to notify");
+            return;
+        }
+    };
+
+    let mut indices: Vec<i64> = recipients
+        .iter()
+        .flat_map(|user| user.favourites.iter().copied())
+        .collect();
+    indices.sort_unstable();
+    indices.dedup();
+
+    let favourites: HashMap<i64, Product> =
+        match state.products.get_products_by_indices(&indices).await {
+            Ok(products) => products
+                .into_iter()
+                .map(|product| (product.index as i64, product))
+                .collect(),
+            Err(error) => {
+                tracing::error!(%error, "Could not fetch favourites to notify about");
+                return;
+            }
+        };
+
+    let snapshot = if subdomain.is_taxfree() {
+        state.metadata.get_price_snapshot(subdomain.name()).await
+    } else {
+        HashMap::new()
+    };
+
+    for user in &recipients {
+        let products: Vec<&Product> = user
+            .favourites
+            .iter()
+            .filter_map(|index| favourites.get(index))
+            .collect();
+        let drops = if subdomain.is_taxfree() {
+            digest::taxfree_drops(&products, &snapshot)
+        } else {
+            digest::vinmonopolet_drops(&products)
+        };
+        if drops.is_empty() {
+            continue;
+        }
+
+        let result = match digest::render_digest(user, subdomain, &drops, &state.config.public_url)
+        {
+            Ok(mail) => state.mailer.send(&mail).await,
+            Err(error) => Err(error),
+        };
+        if let Err(error) = result {
+            tracing::error!(%error, user_id = %user.user_id, "Could not notify user");
+        }
+    }
+
+    if subdomain.is_taxfree() {
+        let products = state
+            .products
+            .get_products(vec![doc! { "$match": { "taxfree.price": { "$gt": 0.0 } } }])
+            .await;
+        // The last snapshot is still the best one to compare with when the catalogue is unreadable.
+        match products {
+            Ok(products) => {
+                let prices: HashMap<String, f64> = products
+                    .into_iter()
+                    .filter_map(|product| Some((product.index.to_string(), product.taxfree?.price)))
+                    .collect();
+                state
+                    .metadata
+                    .set_price_snapshot(subdomain.name(), &prices)
+                    .await;
+            }
+            Err(error) => tracing::error!(%error, "Could not snapshot tax-free prices"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use database::memory::MemoryStore;
+    use mongodb::bson::{Document, oid::ObjectId};
+    use shared::{
+        config::Config,
+        errors::AppError,
+        mail::{Mail, Mailer},
+        models::User,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct RecordingMailer(Mutex<Vec<Mail>>);
+
+    #[async_trait]
+    impl Mailer for RecordingMailer {
+        async fn send(&self, mail: &Mail) -> Result<(), AppError> {
+            self.0.lock().unwrap().push(mail.clone());
+            Ok(())
+        }
+    }
+
+    fn product(index: i64, taxfree: f64) -> Document {
+        doc! {
+            "index": index,
+            "name": format!("Gin {index}"),
+            "price": 400.0,
+            "prices": [400.0],
+            "discount": 0.0,
+            "volume": 70.0,
+            "alcohol": 40.0,
+            "literprice": 400.0 / 0.7,
+            "url": "https://example.com",
+            "stores": [],
+            "category": "Brennevin",
+            "country": "Norge",
+            "updated": true,
+            "taxfree": {
+                "url": "https://example.com/taxfree",
+                "price": taxfree,
+                "discount": 0.0,
+                "stores": ["Gardermoen"],
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn taxfree_snapshot_covers_the_whole_catalogue() {
+        let mailer = Arc::new(RecordingMailer::default());
+        let state = AppState::from_store(
+            MemoryStore::with_products(vec![product(1, 250.0), product(2, 300.0)]),
+            Config::default(),
+            mailer.clone(),
+        );
+        let user = User {
+            user_id: ObjectId::new(),
+            username: "snubler".to_string(),
+            password: String::new(),
+            email: "snubler@example.com".to_string(),
+            favourites: vec![1],
+            notify: true,
+            verified: true,
+            lists: vec![],
+        };
+        state.users.create_user(&user).await.unwrap();
+
+        send_digests(&state, &Subdomain::Taxfree).await;
+        assert!(mailer.0.lock().unwrap().is_empty());
+        let snapshot = state.metadata.get_price_snapshot("taxfree").await;
+        assert_eq!(snapshot.get("1"), Some(&250.0));
+        assert_eq!(snapshot.get("2"), Some(&300.0));
+
+        state
+            .metadata
+            .set_price_snapshot("taxfree", &HashMap::from([("1".to_string(), 280.0)]))
+            .await;
+        send_digests(&state, &Subdomain::Taxfree).await;
+        let mails = mailer.0.lock().unwrap();
+        assert_eq!(mails.len(), 1);
+        assert!(mails[0].body.contains("Gin 1"));
+    }
+}
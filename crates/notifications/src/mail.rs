@@ -0,0 +1,147 @@
+use async_trait::async_trait;
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    message::{Mailbox, header::ContentType},
+    transport::smtp::authentication::Credentials,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs;
+
+use shared::{
+    config::MailConfig,
+    errors::AppError,
+    mail::{Mail, Mailer},
+};
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(host: &str, username: &str, password: &str, from: &str) -> Result<Self, AppError> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+            .map_err(|error| AppError::MailError(error.to_string()))?
+            .credentials(Credentials::new(username.to_string(), password.to_string()))
+            .build();
+        let from = from
+            .parse()
+            .map_err(|_| AppError::MailError(format!("Invalid sender address: {}", from)))?;
+
+        Ok(SmtpMailer { transport, from })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), AppError> {
+        let to: Mailbox = mail
+            .to
+            .parse()
+            .map_err(|_| AppError::MailError(format!("Invalid address: {}", mail.to)))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(&mail.subject)
+            .header(ContentType::TEXT_HTML)
+            .body(mail.body.clone())
+            .map_err(|error| AppError::MailError(error.to_string()))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map_err(|error| AppError::MailError(error.to_string()))?;
+        Ok(())
+    }
+}
+
+/// Writes each mail to a file in `dir` instead of sending it, for local testing.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileMailer { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), AppError> {
+        fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|error| AppError::MailError(error.to_string()))?;
+
+        let recipient: String = mail
+            .to
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let path = self.dir.join(format!(
+            "{}-{}.html",
+            chrono::Local::now().format("%Y%m%d%H%M%S%f"),
+            recipient
+        ));
+
+        let contents = format!(
+            "<!-- To: {} -->\n<!-- Subject: {} -->\n{}",
+            mail.to, mail.subject, mail.body
+        );
+        fs::write(&path, contents)
+            .await
+            .map_err(|error| AppError::MailError(error.to_string()))?;
+
+        tracing::info!(path = %path.display(), "Mail written to disk");
+        Ok(())
+    }
+}
+
+/// SMTP when a host is configured, otherwise mail is written to the mail directory.
+pub fn from_config(config: &MailConfig) -> Result<Arc<dyn Mailer>, AppError> {
+    match &config.smtp_host {
+        Some(host) => Ok(Arc::new(SmtpMailer::new(
+            host,
+            &config.smtp_username,
+            &config.smtp_password,
+            &config.from,
+        )?)),
+        None => Ok(Arc::new(FileMailer::new(&config.dir))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn file_mailer_writes_mail_to_directory() {
+        let dir = std::env::temp_dir().join(format!("snublejuice-mail-{}", std::process::id()));
+        let mailer = FileMailer::new(&dir);
+        let mail = Mail {
+            to: "snubler@example.com".to_string(),
+            subject: "Emne".to_string(),
+            body: "<p>Innhold</p>".to_string(),
+        };
+
+        mailer.send(&mail).await.unwrap();
+
+        let mut entries = std::fs::read_dir(&dir).unwrap();
+        let path = entries.next().unwrap().unwrap().path();
+        assert!(path.to_string_lossy().ends_with("snubler_example_com.html"));
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("Subject: Emne"));
+        assert!(contents.contains("<p>Innhold</p>"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn smtp_mailer_rejects_invalid_sender() {
+        assert!(matches!(
+            SmtpMailer::new("smtp.example.com", "user", "pass", "not an address"),
+            Err(AppError::MailError(_))
+        ));
+    }
+}
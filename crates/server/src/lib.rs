@@ -0,0 +1,1513 @@
+use axum::{Router, middleware, routing::get};
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+
+use shared::state::AppState;
+
+pub mod telemetry;
+
+/// The merged application router, shared by the server binary and the end-to-end tests.
+///
+/// Every request gets an `x-request-id` (kept if the client sent one) and runs inside a span
+/// carrying it, so events logged further down can be tied back to the request.
+pub fn build_app(state: AppState) -> Router {
+    telemetry::recorder();
+
+    Router::<AppState>::new()
+        .merge(frontend::router())
+        .merge(authentication::router())
+        .merge(api::router(state.clone()))
+        .route("/metrics", get(telemetry::serve_metrics))
+        .with_state(state)
+        .layer(middleware::from_fn(telemetry::track))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(telemetry::make_span)
+                .on_request(())
+                .on_response(telemetry::on_response),
+        )
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use axum::{
+        body::{Body, to_bytes},
+        http::{Method, Request, StatusCode, header},
+        response::Response,
+    };
+    use database::memory::MemoryStore;
+    use mongodb::bson::{DateTime, Document, doc};
+    use serde_json::{Value, json};
+    use shared::{
+        config::Config,
+        errors::AppError,
+        mail::{Mail, Mailer},
+        models::AlertField,
+    };
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+
+    const HOST: &str = "vinmonopolet.snublejuice.localhost";
+
+    #[derive(Default)]
+    struct RecordingMailer(Mutex<Vec<Mail>>);
+
+    #[async_trait]
+    impl Mailer for RecordingMailer {
+        async fn send(&self, mail: &Mail) -> Result<(), AppError> {
+            self.0.lock().unwrap().push(mail.clone());
+            Ok(())
+        }
+    }
+
+    /// Waits for the mail sent in the background by a handler, if it sends one.
+    async fn sent_mail(mailer: &RecordingMailer) -> Option<Mail> {
+        for _ in 0..100 {
+            if let Some(mail) = mailer.0.lock().unwrap().pop() {
+                return Some(mail);
+            }
+            tokio::task::yield_now().await;
+        }
+        None
+    }
+
+    struct FailingMailer;
+
+    #[async_trait]
+    impl Mailer for FailingMailer {
+        async fn send(&self, _mail: &Mail) -> Result<(), AppError> {
+            Err(AppError::MailError("unreachable".to_string()))
+        }
+    }
+
+    fn app_with_mailer() -> (Router, Arc<RecordingMailer>) {
+        let mailer = Arc::new(RecordingMailer::default());
+        let state = AppState::from_store(MemoryStore::new(), Config::default(), mailer.clone());
+        (build_app(state), mailer)
+    }
+
+    fn app() -> Router {
+        app_with_mailer().0
+    }
+
+    fn product(index: i64, name: &str, price: f64) -> Document {
+        doc! {
+            "index": index,
+            "name": name,
+            "price": price,
+            "prices": [price + 30.0, price],
+            "discount": -7.1,
+            "volume": 75.0,
+            "alcohol": 14.0,
+            "literprice": price / 0.75,
+            "url": format!("https://example.com/{index}"),
+            "stores": [],
+            "category": "Rødvin",
+            "country": "Italia",
+            "updated": true,
+        }
+    }
+
+    fn app_with_products(products: Vec<Document>) -> Router {
+        let state = AppState::from_store(
+            MemoryStore::with_products(products),
+            Config::default(),
+            Arc::new(RecordingMailer::default()),
+        );
+        build_app(state)
+    }
+
+    async fn send(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        cookie: Option<&str>,
+        body: Value,
+    ) -> Response {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::HOST, HOST)
+            .header("x-forwarded-for", "127.0.0.1")
+            .header(header::USER_AGENT, "snublejuice-test")
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        app.clone()
+            .oneshot(request.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn json_body(response: Response) -> Value {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    /// The `session_id=<value>` pair from the `Set-Cookie` header, asserting its attributes.
+    fn session_cookie(response: &Response) -> String {
+        let set_cookie = response
+            .headers()
+            .get(header::SET_COOKIE)
+            .expect("missing Set-Cookie header")
+            .to_str()
+            .unwrap();
+        assert!(set_cookie.starts_with("session_id="));
+        assert!(set_cookie.contains("HttpOnly"));
+        assert!(set_cookie.contains("Secure"));
+        assert!(set_cookie.contains("Path=/"));
+        set_cookie.split(';').next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn account_lifecycle_through_merged_router() {
+        let app = app();
+        let credentials = json!({ "username": "ola", "password": "hemmelig" });
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/signup",
+            None,
+            json!({ "username": "ola", "password": "hemmelig", "email": "ola@example.com" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        session_cookie(&response);
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/login",
+            None,
+            json!({ "username": "ola", "password": "feil" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "Unauthorized" })
+        );
+
+        let response = send(&app, Method::POST, "/account/login", None, credentials).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let cookie = session_cookie(&response);
+        assert_eq!(json_body(response).await, json!("ok"));
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/favourite",
+            Some(&cookie),
+            json!({ "index": 12345 }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(
+            &app,
+            Method::GET,
+            "/account/favourites",
+            Some(&cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await, json!([12345]));
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/logout",
+            Some(&cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let removed = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(removed.starts_with("session_id=;"));
+
+        let response = send(
+            &app,
+            Method::GET,
+            "/account/favourites",
+            Some(&cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "Unauthorized" })
+        );
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/login",
+            None,
+            json!({ "username": "ola", "password": "hemmelig" }),
+        )
+        .await;
+        let cookie = session_cookie(&response);
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/delete",
+            Some(&cookie),
+            json!({ "password": "feil" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/delete",
+            Some(&cookie),
+            json!({ "password": "hemmelig" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(&app, Method::GET, "/account", Some(&cookie), Value::Null).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/login",
+            None,
+            json!({ "username": "ola", "password": "hemmelig" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(json_body(response).await, json!({ "error": "Not found" }));
+    }
+
+    #[tokio::test]
+    async fn data_routes_report_errors_as_json() {
+        let app = app();
+
+        let response = send(&app, Method::GET, "/data/product/abc", None, Value::Null).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "Ugyldig index." })
+        );
+
+        let response = send(&app, Method::GET, "/data/product/1", None, Value::Null).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = send(&app, Method::GET, "/data/products", None, Value::Null).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await["total"], json!(0));
+
+        let response = send(&app, Method::GET, "/", None, Value::Null).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn product_page_only_accepts_plain_indices() {
+        let app = app_with_products(vec![product(1, "Barolo Riserva", 390.0)]);
+
+        for (uri, status) in [
+            ("/produkt/1", StatusCode::OK),
+            ("/produkt/-1", StatusCode::NOT_FOUND),
+            ("/produkt/+1", StatusCode::NOT_FOUND),
+        ] {
+            let response = send(&app, Method::GET, uri, None, Value::Null).await;
+            assert_eq!(response.status(), status, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn listing_reports_database_errors_instead_of_no_matches() {
+        let state = AppState::from_store(
+            MemoryStore::with_products(vec![product(1, "Barolo", 300.0)])
+                .with_unavailable_products(),
+            Config::default(),
+            Arc::new(RecordingMailer::default()),
+        );
+        let app = build_app(state);
+
+        let response = send(&app, Method::GET, "/data/products", None, Value::Null).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "Internal server error" })
+        );
+
+        let response = send(&app, Method::GET, "/", None, Value::Null).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let html = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(html.contains("Kunne ikke hente produktene."));
+        assert!(!html.contains("treff"));
+    }
+
+    #[tokio::test]
+    async fn password_reset_with_emailed_token() {
+        let (app, mailer) = app_with_mailer();
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/signup",
+            None,
+            json!({ "username": "kari", "password": "glemt", "email": "kari@example.com" }),
+        )
+        .await;
+        let cookie = session_cookie(&response);
+        let verification = mailer.0.lock().unwrap().pop().unwrap();
+        assert_eq!(verification.subject, "Bekreft e-postadressen din");
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/forgot",
+            None,
+            json!({ "email": "ukjent@example.com" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(sent_mail(&mailer).await.is_none());
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/forgot",
+            None,
+            json!({ "email": "KARI@example.com" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let mail = sent_mail(&mailer).await.unwrap();
+        assert_eq!(mail.to, "kari@example.com");
+        let token = mail
+            .body
+            .split("?reset=")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap()
+            .to_string();
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/reset",
+            None,
+            json!({ "token": token, "password": "nytt-passord" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The token is single-use, and existing sessions are logged out.
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/reset",
+            None,
+            json!({ "token": token, "password": "enda-et" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "Lenken er ugyldig eller utløpt." })
+        );
+        let response = send(&app, Method::GET, "/account", Some(&cookie), Value::Null).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/login",
+            None,
+            json!({ "username": "kari", "password": "nytt-passord" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Recovery allows a burst of five requests per address.
+        for status in [StatusCode::OK, StatusCode::TOO_MANY_REQUESTS] {
+            let response = send(
+                &app,
+                Method::POST,
+                "/account/forgot",
+                None,
+                json!({ "email": "kari@example.com" }),
+            )
+            .await;
+            assert_eq!(response.status(), status);
+        }
+    }
+
+    #[tokio::test]
+    async fn forgot_does_not_reveal_accounts_when_mail_fails() {
+        let state = AppState::from_store(
+            MemoryStore::new(),
+            Config::default(),
+            Arc::new(FailingMailer),
+        );
+        let app = build_app(state);
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/signup",
+            None,
+            json!({ "username": "kari", "password": "glemt", "email": "kari@example.com" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        for email in ["kari@example.com", "ukjent@example.com"] {
+            let response = send(
+                &app,
+                Method::POST,
+                "/account/forgot",
+                None,
+                json!({ "email": email }),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(json_body(response).await, json!("ok"));
+        }
+    }
+
+    #[tokio::test]
+    async fn signup_validates_email_and_verification_link_confirms_it() {
+        let (app, mailer) = app_with_mailer();
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/signup",
+            None,
+            json!({ "username": "per", "password": "hemmelig", "email": "per@example" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "Ugyldig e-postadresse." })
+        );
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/signup",
+            None,
+            json!({ "username": "per", "password": "x", "email": "per@example.com" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "Passordet må være minst 4 tegn." })
+        );
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/signup",
+            None,
+            json!({ "username": "per", "password": "hemmelig", "email": " per@example.com " }),
+        )
+        .await;
+        let cookie = session_cookie(&response);
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/signup",
+            None,
+            json!({ "username": "pål", "password": "hemmelig", "email": "Per@Example.com" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "E-postadressen er allerede i bruk." })
+        );
+
+        let response = send(&app, Method::GET, "/account", Some(&cookie), Value::Null).await;
+        let user = json_body(response).await;
+        assert_eq!(user["email"], json!("per@example.com"));
+        assert_eq!(user["verified"], json!(false));
+
+        let mail = mailer.0.lock().unwrap().pop().unwrap();
+        assert_eq!(mail.to, "per@example.com");
+        let link = mail
+            .body
+            .split("href=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap()
+            .to_string();
+        let path = link.trim_start_matches(Config::default().public_url.as_str());
+        assert!(path.starts_with("/account/verify?token="));
+
+        for expected in ["/?verified=true", "/?verified=false"] {
+            let response = send(&app, Method::GET, path, None, Value::Null).await;
+            assert_eq!(response.status(), StatusCode::SEE_OTHER);
+            assert_eq!(response.headers()[header::LOCATION], expected);
+        }
+
+        let response = send(&app, Method::GET, "/account", Some(&cookie), Value::Null).await;
+        assert_eq!(json_body(response).await["verified"], json!(true));
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/verify",
+            Some(&cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn sessions_coexist_and_can_be_revoked() {
+        let app = app();
+        let login = json!({ "username": "eva", "password": "hemmelig" });
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/signup",
+            None,
+            json!({ "username": "eva", "password": "hemmelig", "email": "eva@example.com" }),
+        )
+        .await;
+        let laptop = session_cookie(&response);
+        let response = send(&app, Method::POST, "/account/login", None, login.clone()).await;
+        let phone = session_cookie(&response);
+
+        let response = send(&app, Method::GET, "/account", Some(&laptop), Value::Null).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(
+            &app,
+            Method::GET,
+            "/account/sessions",
+            Some(&phone),
+            Value::Null,
+        )
+        .await;
+        let sessions = json_body(response).await;
+        let sessions = sessions.as_array().unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert!(
+            sessions
+                .iter()
+                .all(|s| s["user_agent"] == "snublejuice-test")
+        );
+        assert!(sessions.iter().all(|s| s["ip"] == "127.0.0.1"));
+        assert!(sessions.iter().all(|s| s.get("session_id").is_none()));
+        let other = sessions.iter().find(|s| s["current"] == false).unwrap();
+
+        let response = send(
+            &app,
+            Method::DELETE,
+            "/account/sessions/ugyldig",
+            Some(&phone),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let uri = format!("/account/sessions/{}", other["id"].as_str().unwrap());
+        let response = send(&app, Method::DELETE, &uri, Some(&phone), Value::Null).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(&app, Method::GET, "/account", Some(&laptop), Value::Null).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = send(&app, Method::POST, "/account/login", None, login).await;
+        let tablet = session_cookie(&response);
+        let response = send(
+            &app,
+            Method::DELETE,
+            "/account/sessions",
+            Some(&phone),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(&app, Method::GET, "/account", Some(&tablet), Value::Null).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = send(
+            &app,
+            Method::GET,
+            "/account/sessions",
+            Some(&phone),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(json_body(response).await.as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_are_rejected() {
+        let state = AppState::from_store(
+            MemoryStore::new(),
+            Config::default(),
+            Arc::new(RecordingMailer::default()),
+        );
+        let app = build_app(state.clone());
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/signup",
+            None,
+            json!({ "username": "ida", "password": "hemmelig", "email": "ida@example.com" }),
+        )
+        .await;
+        let cookie = session_cookie(&response);
+        let session_id = cookie.trim_start_matches("session_id=");
+
+        let mut session = state.sessions.get_session(session_id).await.unwrap();
+        state.sessions.delete_session(session_id).await.unwrap();
+        session.expires_after = DateTime::from_millis(DateTime::now().timestamp_millis() - 1000);
+        state.sessions.store_session(session).await.unwrap();
+
+        let response = send(&app, Method::GET, "/account", Some(&cookie), Value::Null).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "Unauthorized" })
+        );
+
+        tokio::task::yield_now().await;
+        assert!(state.sessions.get_session(session_id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn session_expiry_slides_in_batches_once_due() {
+        let state = AppState::from_store(
+            MemoryStore::new(),
+            Config::default(),
+            Arc::new(RecordingMailer::default()),
+        );
+        let app = build_app(state.clone());
+
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/signup",
+            None,
+            json!({ "username": "siv", "password": "hemmelig", "email": "siv@example.com" }),
+        )
+        .await;
+        let cookie = session_cookie(&response);
+        let session_id = cookie.trim_start_matches("session_id=").to_string();
+
+        // A fresh session is not written to on every request.
+        send(&app, Method::GET, "/account", Some(&cookie), Value::Null).await;
+        assert!(state.refreshes.take().is_empty());
+
+        let mut session = state.sessions.get_session(&session_id).await.unwrap();
+        state.sessions.delete_session(&session_id).await.unwrap();
+        let soon = DateTime::from_millis(DateTime::now().timestamp_millis() + 60 * 60 * 1000);
+        session.expires_after = soon;
+        state.sessions.store_session(session).await.unwrap();
+
+        for _ in 0..3 {
+            let response = send(&app, Method::GET, "/account", Some(&cookie), Value::Null).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        database::jobs::flush_session_refreshes(&state).await;
+
+        let session = state.sessions.get_session(&session_id).await.unwrap();
+        assert!(
+            session.expires_after.timestamp_millis() > soon.timestamp_millis() + 60 * 60 * 1000
+        );
+        assert!(state.refreshes.take().is_empty());
+    }
+
+    #[tokio::test]
+    async fn account_settings_require_current_password() {
+        let (app, mailer) = app_with_mailer();
+
+        for (username, email) in [("tor", "tor@example.com"), ("liv", "liv@example.com")] {
+            send(
+                &app,
+                Method::POST,
+                "/account/signup",
+                None,
+                json!({ "username": username, "password": "hemmelig", "email": email }),
+            )
+            .await;
+        }
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/login",
+            None,
+            json!({ "username": "tor", "password": "hemmelig" }),
+        )
+        .await;
+        let other_device = session_cookie(&response);
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/login",
+            None,
+            json!({ "username": "tor", "password": "hemmelig" }),
+        )
+        .await;
+        let cookie = session_cookie(&response);
+
+        let change = |uri: &'static str, body: Value| {
+            let app = app.clone();
+            let cookie = cookie.clone();
+            async move { send(&app, Method::POST, uri, Some(&cookie), body).await }
+        };
+
+        let response = change(
+            "/account/username",
+            json!({ "password": "feil", "username": "thor" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = change(
+            "/account/username",
+            json!({ "password": "hemmelig", "username": "liv" }),
+        )
+        .await;
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "Brukeren finnes allerede." })
+        );
+        let response = change(
+            "/account/username",
+            json!({ "password": "hemmelig", "username": "thor" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = change(
+            "/account/email",
+            json!({ "password": "hemmelig", "email": "LIV@example.com" }),
+        )
+        .await;
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "E-postadressen er allerede i bruk." })
+        );
+        mailer.0.lock().unwrap().clear();
+        let response = change(
+            "/account/email",
+            json!({ "password": "hemmelig", "email": "thor@example.com" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(mailer.0.lock().unwrap()[0].to, "thor@example.com");
+
+        let response = change(
+            "/account/password",
+            json!({ "password": "hemmelig", "new_password": "abc" }),
+        )
+        .await;
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "Passordet må være minst 4 tegn." })
+        );
+        let response = change(
+            "/account/password",
+            json!({ "password": "hemmelig", "new_password": "nytt-passord" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(
+            &app,
+            Method::GET,
+            "/account",
+            Some(&other_device),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = send(&app, Method::GET, "/account", Some(&cookie), Value::Null).await;
+        let user = json_body(response).await;
+        assert_eq!(user["username"], "thor");
+        assert_eq!(user["email"], "thor@example.com");
+        assert_eq!(user["verified"], false);
+    }
+
+    #[tokio::test]
+    async fn export_bundles_account_data_without_password() {
+        let app = app_with_products(vec![product(7, "Barolo Riserva", 390.0)]);
+
+        send(
+            &app,
+            Method::POST,
+            "/account/signup",
+            None,
+            json!({ "username": "tor", "password": "hemmelig", "email": "tor@example.com" }),
+        )
+        .await;
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/login",
+            None,
+            json!({ "username": "tor", "password": "hemmelig" }),
+        )
+        .await;
+        let cookie = session_cookie(&response);
+
+        let response = send(&app, Method::GET, "/account/export", None, Value::Null).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        for index in [7, 99] {
+            send(
+                &app,
+                Method::POST,
+                "/account/favourite",
+                Some(&cookie),
+                json!({ "index": index }),
+            )
+            .await;
+        }
+        let list = json_body(
+            send(
+                &app,
+                Method::POST,
+                "/account/lists",
+                Some(&cookie),
+                json!({ "name": "Julebord" }),
+            )
+            .await,
+        )
+        .await;
+        send(
+            &app,
+            Method::POST,
+            &format!("/account/lists/{}/entries", list["id"].as_str().unwrap()),
+            Some(&cookie),
+            json!({ "index": 7, "note": "Til pinnekjøttet" }),
+        )
+        .await;
+
+        let response = send(
+            &app,
+            Method::GET,
+            "/account/export",
+            Some(&cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(
+            response.headers()[header::CONTENT_DISPOSITION]
+                .to_str()
+                .unwrap()
+                .starts_with("attachment")
+        );
+        let bundle = json_body(response).await;
+
+        assert_eq!(bundle["user"]["username"], "tor");
+        assert_eq!(bundle["user"]["email"], "tor@example.com");
+        assert!(bundle["user"].get("password").is_none());
+        assert!(!bundle.to_string().contains("$2"));
+
+        let sessions = bundle["sessions"].as_array().unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(
+            sessions
+                .iter()
+                .filter(|session| session["current"] == true)
+                .count(),
+            1
+        );
+        assert_eq!(sessions[0]["user_agent"], "snublejuice-test");
+
+        assert_eq!(
+            bundle["favourites"],
+            json!([
+                {
+                    "index": 7,
+                    "name": "Barolo Riserva",
+                    "price": 390.0,
+                    "literprice": 520.0,
+                    "url": "https://example.com/7",
+                },
+                { "index": 99, "name": null, "price": null, "literprice": null, "url": null },
+            ])
+        );
+        let entry = &bundle["lists"][0]["entries"][0];
+        assert_eq!(bundle["lists"][0]["name"], "Julebord");
+        assert_eq!(
+            (entry["index"].clone(), entry["note"].clone()),
+            (json!(7), json!("Til pinnekjøttet"))
+        );
+        assert_eq!(
+            (entry["name"].clone(), entry["price"].clone()),
+            (json!("Barolo Riserva"), json!(390.0))
+        );
+        assert_eq!(
+            bundle["preferences"],
+            json!({ "notify": false, "searches": [] })
+        );
+    }
+
+    #[tokio::test]
+    async fn export_fails_rather_than_losing_products() {
+        let state = AppState::from_store(
+            MemoryStore::with_products(vec![product(7, "Barolo Riserva", 390.0)])
+                .with_unavailable_products(),
+            Config::default(),
+            Arc::new(RecordingMailer::default()),
+        );
+        let app = build_app(state);
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/signup",
+            None,
+            json!({ "username": "tor", "password": "hemmelig", "email": "tor@example.com" }),
+        )
+        .await;
+        let cookie = session_cookie(&response);
+
+        let response = send(
+            &app,
+            Method::GET,
+            "/account/export",
+            Some(&cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn favourite_lists_filter_listing_and_keep_entries_when_moved() {
+        let app = app_with_products(vec![
+            product(1, "Barolo Riserva", 390.0),
+            product(2, "Chablis", 250.0),
+            product(3, "Rioja", 180.0),
+        ]);
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/signup",
+            None,
+            json!({ "username": "tor", "password": "hemmelig", "email": "tor@example.com" }),
+        )
+        .await;
+        let cookie = session_cookie(&response);
+        let request = |method: Method, uri: String, body: Value| {
+            let app = app.clone();
+            let cookie = cookie.clone();
+            async move { send(&app, method, &uri, Some(&cookie), body).await }
+        };
+
+        send(
+            &app,
+            Method::POST,
+            "/account/favourite",
+            Some(&cookie),
+            json!({ "index": 3 }),
+        )
+        .await;
+
+        let julebord = json_body(
+            request(
+                Method::POST,
+                "/account/lists".into(),
+                json!({ "name": "Julebord" }),
+            )
+            .await,
+        )
+        .await;
+        let julebord = julebord["id"].as_str().unwrap().to_string();
+        let cellar = json_body(
+            request(
+                Method::POST,
+                "/account/lists".into(),
+                json!({ "name": "Kjeller" }),
+            )
+            .await,
+        )
+        .await;
+        let cellar = cellar["id"].as_str().unwrap().to_string();
+        let response = request(
+            Method::POST,
+            "/account/lists".into(),
+            json!({ "name": "kjeller" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let list = json_body(
+            request(
+                Method::POST,
+                format!("/account/lists/{julebord}/entries"),
+                json!({ "index": 1, "note": " Til pinnekjøttet ", "target_price": 350.0 }),
+            )
+            .await,
+        )
+        .await;
+        assert_eq!(list["entries"][0]["note"], "Til pinnekjøttet");
+        assert_eq!(list["entries"][0]["targetPrice"], 350.0);
+        request(
+            Method::POST,
+            format!("/account/lists/{julebord}/entries"),
+            json!({ "index": 2 }),
+        )
+        .await;
+        let response = request(
+            Method::POST,
+            format!("/account/lists/{julebord}/entries"),
+            json!({ "index": 99 }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let indices = |body: Value| -> Vec<i64> {
+            body["products"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|product| product["index"].as_i64().unwrap())
+                .collect()
+        };
+        let response = request(
+            Method::GET,
+            format!("/data/products?favourites=true&list={julebord}&sort=price&ascending=true"),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(indices(json_body(response).await), vec![2, 1]);
+        let response = request(
+            Method::GET,
+            "/data/products?favourites=true".into(),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(indices(json_body(response).await), vec![3]);
+
+        let response = request(
+            Method::POST,
+            format!("/account/lists/{julebord}/move"),
+            json!({ "index": 1, "to": cellar }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = request(
+            Method::PUT,
+            format!("/account/lists/{cellar}"),
+            json!({ "name": "Vinkjeller" }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let lists =
+            json_body(request(Method::GET, "/account/lists".into(), Value::Null).await).await;
+        assert_eq!(lists[0]["entries"].as_array().unwrap().len(), 1);
+        assert_eq!(lists[1]["name"], "Vinkjeller");
+        assert_eq!(lists[1]["entries"][0]["index"], 1);
+        assert_eq!(lists[1]["entries"][0]["note"], "Til pinnekjøttet");
+
+        let response = request(
+            Method::DELETE,
+            format!("/account/lists/{julebord}/entries/1"),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = request(
+            Method::DELETE,
+            format!("/account/lists/{julebord}"),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let lists =
+            json_body(request(Method::GET, "/account/lists".into(), Value::Null).await).await;
+        assert_eq!(lists.as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn shared_list_is_public_until_revoked() {
+        let mut taxfree = product(2, "Gin", 400.0);
+        taxfree.insert(
+            "taxfree",
+            doc! { "url": "https://example.com/tax", "price": 250.0, "discount": -30.0, "stores": [] },
+        );
+        let app = app_with_products(vec![product(1, "Barolo Riserva", 390.0), taxfree]);
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/signup",
+            None,
+            json!({ "username": "tor", "password": "hemmelig", "email": "tor@example.com" }),
+        )
+        .await;
+        let cookie = session_cookie(&response);
+
+        let list = json_body(
+            send(
+                &app,
+                Method::POST,
+                "/account/lists",
+                Some(&cookie),
+                json!({ "name": "Taxfree-tur" }),
+            )
+            .await,
+        )
+        .await;
+        let id = list["id"].as_str().unwrap().to_string();
+        assert!(list.get("slug").is_none());
+        for index in [2, 1] {
+            send(
+                &app,
+                Method::POST,
+                &format!("/account/lists/{id}/entries"),
+                Some(&cookie),
+                json!({ "index": index, "note": "privat" }),
+            )
+            .await;
+        }
+
+        let list = json_body(
+            send(
+                &app,
+                Method::POST,
+                &format!("/account/lists/{id}/share"),
+                Some(&cookie),
+                Value::Null,
+            )
+            .await,
+        )
+        .await;
+        let slug = list["slug"].as_str().unwrap().to_string();
+        assert_eq!(slug.len(), 64);
+        let again = json_body(
+            send(
+                &app,
+                Method::POST,
+                &format!("/account/lists/{id}/share"),
+                Some(&cookie),
+                Value::Null,
+            )
+            .await,
+        )
+        .await;
+        assert_eq!(again["slug"], slug.as_str());
+
+        let page = |host: &'static str, slug: String| {
+            let app = app.clone();
+            async move {
+                let request = Request::builder()
+                    .uri(format!("/liste/{slug}"))
+                    .header(header::HOST, host)
+                    .header("x-forwarded-for", "127.0.0.1")
+                    .body(Body::empty())
+                    .unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, String::from_utf8(bytes.to_vec()).unwrap())
+            }
+        };
+
+        let (status, html) = page(HOST, slug.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(html.contains("Taxfree-tur"));
+        assert!(html.find("Gin").unwrap() < html.find("Barolo Riserva").unwrap());
+        assert!(!html.contains("privat"));
+        assert!(!html.contains("favourite-toggle"));
+
+        let (status, html) = page("taxfree.snublejuice.localhost", slug.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(html.contains("Gin"));
+        assert!(!html.contains("Barolo Riserva"));
+
+        let (status, _) = page("snublejuice.localhost", slug.clone()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = page(HOST, "ukjent".to_string()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let response = send(
+            &app,
+            Method::DELETE,
+            &format!("/account/lists/{id}/share"),
+            Some(&cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let (status, _) = page(HOST, slug).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn alerts_are_managed_per_user_and_dismissed_once_seen() {
+        let state = AppState::from_store(
+            MemoryStore::with_products(vec![product(1, "Barolo Riserva", 390.0)]),
+            Config::default(),
+            Arc::new(RecordingMailer::default()),
+        );
+        let app = build_app(state.clone());
+        let response = send(
+            &app,
+            Method::POST,
+            "/account/signup",
+            None,
+            json!({ "username": "tor", "password": "hemmelig", "email": "tor@example.com" }),
+        )
+        .await;
+        let cookie = session_cookie(&response);
+        let request = |method: Method, uri: &'static str, body: Value| {
+            let app = app.clone();
+            let cookie = cookie.clone();
+            async move { send(&app, method, uri, Some(&cookie), body).await }
+        };
+
+        let response = request(
+            Method::POST,
+            "/account/alerts",
+            json!({ "index": 1, "field": "price", "threshold": 0.0 }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = request(
+            Method::POST,
+            "/account/alerts",
+            json!({ "index": 99, "field": "price", "threshold": 300.0 }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = request(
+            Method::POST,
+            "/account/alerts",
+            json!({ "index": 1, "field": "taxfree", "threshold": 300.0 }),
+        )
+        .await;
+        assert_eq!(
+            json_body(response).await,
+            json!({ "error": "Produktet har ingen slik pris." })
+        );
+
+        let alert = json_body(
+            request(
+                Method::POST,
+                "/account/alerts",
+                json!({ "index": 1, "field": "literprice", "threshold": 500.0 }),
+            )
+            .await,
+        )
+        .await;
+        assert_eq!(alert["value"], 520.0);
+        assert_eq!(alert["pending"], false);
+
+        // Stand in for a monthly update that lowers the price below the threshold.
+        let mut fired = state
+            .users
+            .get_alerts_for_fields(&[AlertField::Literprice])
+            .await
+            .unwrap();
+        assert!(fired[0].evaluate(Some(480.0)));
+        state.users.record_alert(&fired[0]).await.unwrap();
+
+        let alerts = json_body(request(Method::GET, "/account/alerts", Value::Null).await).await;
+        assert_eq!(alerts[0]["pending"], true);
+        assert_eq!(alerts[0]["value"], 480.0);
+        request(Method::POST, "/account/alerts/seen", Value::Null).await;
+        let alerts = json_body(request(Method::GET, "/account/alerts", Value::Null).await).await;
+        assert_eq!(alerts[0]["pending"], false);
+
+        let export = json_body(request(Method::GET, "/account/export", Value::Null).await).await;
+        assert_eq!(export["alerts"].as_array().unwrap().len(), 1);
+
+        let id = fired[0].alert_id.to_hex();
+        let response = send(
+            &app,
+            Method::DELETE,
+            &format!("/account/alerts/{id}"),
+            Some(&cookie),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let alerts = json_body(request(Method::GET, "/account/alerts", Value::Null).await).await;
+        assert!(alerts.as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn responses_carry_a_request_id() {
+        let app = app();
+
+        let response = send(&app, Method::GET, "/", None, Value::Null).await;
+        let id = response.headers().get("x-request-id").unwrap();
+        assert!(!id.is_empty());
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/account")
+                    .header(header::HOST, HOST)
+                    .header("x-forwarded-for", "127.0.0.1")
+                    .header("x-request-id", "from-the-proxy")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["x-request-id"], "from-the-proxy");
+    }
+
+    #[tokio::test]
+    async fn metrics_require_the_configured_token() {
+        async fn scrape(app: &Router, token: Option<&str>) -> Response {
+            let mut request = Request::builder()
+                .uri("/metrics")
+                .header(header::HOST, HOST);
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+            }
+            app.clone()
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap()
+        }
+
+        let hidden = app();
+        assert_eq!(
+            scrape(&hidden, Some("secret")).await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        let config = Config {
+            metrics_token: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let state = AppState::from_store(
+            MemoryStore::new(),
+            config,
+            Arc::new(RecordingMailer::default()),
+        );
+        let app = build_app(state);
+
+        let login = json!({ "username": "nobody", "password": "wrong" });
+        let response = send(&app, Method::POST, "/account/login", None, login).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        assert_eq!(scrape(&app, None).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            scrape(&app, Some("guess")).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let response = scrape(&app, Some("secret")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(body.contains(r#"login_failures_total{reason="unknown_user"}"#));
+        assert!(body.contains(r#"route="/account/login""#));
+        assert!(body.contains("http_request_duration_seconds_bucket"));
+        assert!(body.contains("active_sessions 0"));
+    }
+
+    #[tokio::test]
+    async fn listing_reports_totals_and_clamps_pages() {
+        let products = (1..=16)
+            .map(|index| {
+                let name = if index % 4 == 0 { "Barolo" } else { "Chianti" };
+                let mut product = product(index, &format!("{name} {index}"), 100.0 + index as f64);
+                product.insert("orderable", true);
+                product
+            })
+            .collect();
+        let app = app_with_products(products);
+        let list = |uri: &'static str| {
+            let app = app.clone();
+            async move { json_body(send(&app, Method::GET, uri, None, Value::Null).await).await }
+        };
+
+        let first = list("/data/products").await;
+        assert_eq!(first["products"].as_array().unwrap().len(), 15);
+        assert_eq!(
+            (first["page"].clone(), first["max_page"].clone()),
+            (json!(1), json!(2))
+        );
+        assert_eq!(first["total"], 16);
+
+        let past = list("/data/products?page=9").await;
+        assert_eq!(past["page"], 2);
+        assert_eq!(past["products"].as_array().unwrap().len(), 1);
+
+        let huge = list("/data/products?page=9223372036854775807").await;
+        assert_eq!(huge["page"], 2);
+        assert_eq!(huge["products"].as_array().unwrap().len(), 1);
+
+        let before = list("/data/products?page=-1").await;
+        assert_eq!(before["page"], 1);
+        assert_eq!(before["products"].as_array().unwrap().len(), 15);
+
+        let search = list("/data/products?search=barolo").await;
+        assert_eq!(
+            (search["total"].clone(), search["max_page"].clone()),
+            (json!(4), json!(1))
+        );
+
+        let response = send(&app, Method::GET, "/?page=9", None, Value::Null).await;
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let html = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(html.contains("2 av 2 · 16 treff"));
+    }
+
+    #[tokio::test]
+    async fn search_results_can_be_sorted() {
+        let app = app_with_products(vec![
+            product(1, "Barolo Riserva", 390.0),
+            product(2, "Barolo Classico", 250.0),
+            product(3, "Barolo Giovane", 320.0),
+            product(4, "Chablis", 200.0),
+        ]);
+        let indices = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let body = json_body(send(&app, Method::GET, uri, None, Value::Null).await).await;
+                assert_eq!(body["total"], 3);
+                body["products"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|product| product["index"].as_i64().unwrap())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            indices("/data/products?search=barolo&sort=price&ascending=false").await,
+            vec![1, 3, 2]
+        );
+        assert_eq!(
+            indices("/data/products?search=barolo&sort=price").await,
+            vec![2, 3, 1]
+        );
+
+        let response = send(&app, Method::GET, "/?search=barolo", None, Value::Null).await;
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let html = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(html.contains(r#"<option value="relevance" selected>Relevans</option>"#));
+    }
+}
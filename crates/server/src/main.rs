@@ -1,36 +1,68 @@
-use axum::Router;
 use axum::serve;
 use std::net::SocketAddr;
 
-use api;
-use authentication;
-use database;
-use frontend;
-use shared::state::AppState;
-
-static _DATABASE_KEY: &str = "MONGODB";
-static _DATABASE_NAME: &str = "snublejuice";
-static _PORT: u16 = 3000;
+use database::{memory::MemoryStore, mongo::MongoStore};
+use shared::{
+    config::{Config, Storage},
+    state::AppState,
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let db = database::connect::get_database(_DATABASE_KEY, _DATABASE_NAME).await?;
-    let state = AppState { db };
+    let config = Config::load()?;
+    server::telemetry::init_tracing(config.is_production());
+    let port = config.port;
+
+    let state = get_state(config).await?;
+
+    tokio::spawn(database::jobs::watch(state.clone()));
+    tokio::spawn(database::jobs::refresh_sessions(state.clone()));
+    tokio::spawn(notifications::watch(state.clone()));
+    tokio::spawn(authentication::auth::ask_existing_users_to_verify(
+        state.clone(),
+    ));
 
-    let app = Router::<AppState>::new()
-        .merge(frontend::router())
-        .merge(authentication::router())
-        .merge(api::router(state.clone()))
-        .with_state(state.clone());
+    let app = server::build_app(state);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], _PORT));
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
 
-    println!("http://snublejuice.localhost:{}", _PORT);
-    println!("http://vinmonopolet.snublejuice.localhost:{}", _PORT);
-    println!("http://taxfree.snublejuice.localhost:{}", _PORT);
+    tracing::info!("http://snublejuice.localhost:{}", port);
+    tracing::info!("http://vinmonopolet.snublejuice.localhost:{}", port);
+    tracing::info!("http://taxfree.snublejuice.localhost:{}", port);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+    serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
+
+/// The in-memory store is optionally seeded from the configured products file. It cannot expire
+/// sessions and tokens on its own, so they are swept periodically instead.
+async fn get_state(config: Config) -> Result<AppState, Box<dyn std::error::Error>> {
+    let mailer = notifications::mail::from_config(&config.mail)?;
+    match config.storage {
+        Storage::Memory => {
+            let store = match &config.products_file {
+                Some(path) => MemoryStore::from_json(&std::fs::read_to_string(path)?)?,
+                None => MemoryStore::new(),
+            };
+            let state = AppState::from_store(store, config, mailer);
+            tokio::spawn(database::jobs::sweep(state.clone()));
+            Ok(state)
+        }
+        Storage::Mongo => {
+            let uri = config.database_uri.as_deref().unwrap_or_default();
+            let db = database::connect::get_database(uri, &config.database_name).await?;
+            database::indexes::ensure_indexes(&db).await;
+            Ok(AppState::from_store(
+                MongoStore::new(db).with_search(config.search),
+                config,
+                mailer,
+            ))
+        }
+    }
+}
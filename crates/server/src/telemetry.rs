@@ -0,0 +1,132 @@
+use authentication::middle::hash_token;
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Request, State},
+    http::{HeaderMap, Response, header},
+    middleware::Next,
+    response::IntoResponse,
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+use tracing::{Span, field::Empty};
+use tracing_subscriber::EnvFilter;
+
+use shared::{errors::AppError, state::AppState, subdomain::Subdomain};
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// JSON lines in production, human-readable output otherwise. `RUST_LOG` overrides the level.
+pub fn init_tracing(production: bool) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    if production {
+        subscriber.json().init();
+    } else {
+        subscriber.pretty().init();
+    }
+}
+
+/// The global Prometheus recorder, installed on first use.
+pub fn recorder() -> &'static PrometheusHandle {
+    static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+    HANDLE.get_or_init(|| {
+        PrometheusBuilder::new()
+            .set_buckets_for_metric(Matcher::Suffix("seconds".to_string()), LATENCY_BUCKETS)
+            .expect("latency buckets are not empty")
+            .install_recorder()
+            .expect("no other metrics recorder is installed")
+    })
+}
+
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> &'a str {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// The query string is left out of the span, as it may carry tokens.
+pub fn make_span(request: &Request) -> Span {
+    let headers = request.headers();
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = request.uri().path(),
+        request_id = header_value(headers, "x-request-id"),
+        subdomain = Subdomain::from_host(header_value(headers, header::HOST.as_str())).name(),
+        user_id = Empty,
+        status = Empty,
+        latency_ms = Empty,
+    )
+}
+
+pub fn on_response(response: &Response<Body>, latency: Duration, span: &Span) {
+    span.record("status", response.status().as_u16());
+    span.record("latency_ms", latency.as_millis() as u64);
+    tracing::info!("Request handled");
+}
+
+/// Counts and times each request by its route pattern rather than the concrete path, so that
+/// product indices and list ids do not each get their own series.
+pub async fn track(request: Request, next: Next) -> Response<Body> {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |path| path.as_str())
+        .to_string();
+    let subdomain = Subdomain::from_host(header_value(request.headers(), header::HOST.as_str()));
+
+    let response = next.run(request).await;
+
+    let labels = [
+        ("method", method),
+        ("route", route),
+        ("subdomain", subdomain.name().to_string()),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    metrics::counter!("http_requests_total", &labels).increment(1);
+    metrics::histogram!("http_request_duration_seconds", &labels[..3])
+        .record(start.elapsed().as_secs_f64());
+
+    response
+}
+
+/// Prometheus scrape endpoint. It is only served when a `metrics_token` is configured, and then
+/// only to requests presenting it as a bearer token.
+pub async fn serve_metrics(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let token = state
+        .config
+        .metrics_token
+        .as_deref()
+        .ok_or(AppError::NotFound)?;
+
+    // Comparing the hashes keeps the comparison time independent of the token itself.
+    let given = header_value(&headers, header::AUTHORIZATION.as_str())
+        .strip_prefix("Bearer ")
+        .map(hash_token);
+    if given != Some(hash_token(token)) {
+        return Err(AppError::Unauthorized);
+    }
+
+    if let Ok(count) = state.sessions.count_active_sessions().await {
+        metrics::gauge!("active_sessions").set(count as f64);
+    }
+
+    let recorder = recorder();
+    recorder.run_upkeep();
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        recorder.render(),
+    ))
+}
@@ -0,0 +1,398 @@
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Environment variable pointing to an optional TOML file. Environment variables take precedence
+/// over values from the file.
+pub static CONFIG_FILE_KEY: &str = "CONFIG_FILE";
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Could not read {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Could not parse the configuration file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Invalid value {value:?} for {key}: {reason}")]
+    Invalid {
+        key: &'static str,
+        value: String,
+        reason: &'static str,
+    },
+    #[error("Missing {0}")]
+    Missing(&'static str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    Development,
+    /// A production-like deployment, such as a staging instance. It is not counted as production,
+    /// so visitors are not recorded.
+    Staging,
+    Production,
+}
+
+impl FromStr for Environment {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "development" => Ok(Environment::Development),
+            "staging" => Ok(Environment::Staging),
+            "production" => Ok(Environment::Production),
+            _ => Err("expected `development`, `staging` or `production`"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    Mongo,
+    Memory,
+}
+
+impl FromStr for Storage {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "mongo" => Ok(Storage::Mongo),
+            "memory" => Ok(Storage::Memory),
+            _ => Err("expected `mongo` or `memory`"),
+        }
+    }
+}
+
+/// How product searches are answered by the MongoDB store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Search {
+    /// The Atlas Search index `name`, falling back to local search if the query fails.
+    Atlas,
+    /// Ranked in-process, for self-hosted or local databases without Atlas Search.
+    Local,
+}
+
+impl FromStr for Search {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "atlas" => Ok(Search::Atlas),
+            "local" => Ok(Search::Local),
+            _ => Err("expected `atlas` or `local`"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    /// Mail is written to `dir` instead of sent when no SMTP host is configured.
+    pub smtp_host: Option<String>,
+    pub smtp_username: String,
+    pub smtp_password: String,
+    pub from: String,
+    pub dir: PathBuf,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            smtp_host: None,
+            smtp_username: String::new(),
+            smtp_password: String::new(),
+            from: "Snublejuice <post@snublejuice.no>".to_string(),
+            dir: PathBuf::from("mail"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub environment: Environment,
+    pub port: u16,
+    pub storage: Storage,
+    pub database_uri: Option<String>,
+    pub database_name: String,
+    pub search: Search,
+    /// JSON array of products to seed the in-memory store with.
+    pub products_file: Option<PathBuf>,
+    pub cookie_domain: String,
+    /// Address of the landing page, used for links in e-mails.
+    pub public_url: String,
+    pub image_dir: Option<PathBuf>,
+    /// Fraction of a session's lifetime that must pass before its expiry slides forward again.
+    /// `0.0` refreshes on every request.
+    pub session_refresh_fraction: f64,
+    /// Bearer token required by `/metrics`. The endpoint is not served when unset.
+    pub metrics_token: Option<String>,
+    pub mail: MailConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            environment: Environment::Development,
+            port: 3000,
+            storage: Storage::Mongo,
+            database_uri: None,
+            database_name: "snublejuice".to_string(),
+            search: Search::Atlas,
+            products_file: None,
+            cookie_domain: "snublejuice.localhost".to_string(),
+            public_url: "http://snublejuice.localhost:3000".to_string(),
+            image_dir: None,
+            session_refresh_fraction: 0.1,
+            metrics_token: None,
+            mail: MailConfig::default(),
+        }
+    }
+}
+
+fn parse<T: FromStr<Err = &'static str>>(
+    key: &'static str,
+    value: String,
+) -> Result<T, ConfigError> {
+    value
+        .parse()
+        .map_err(|reason| ConfigError::Invalid { key, value, reason })
+}
+
+impl Config {
+    /// Reads the file in `CONFIG_FILE` (if set), then applies the environment on top.
+    pub fn load() -> Result<Self, ConfigError> {
+        let file = match std::env::var(CONFIG_FILE_KEY) {
+            Ok(path) => Some(
+                std::fs::read_to_string(&path)
+                    .map_err(|source| ConfigError::Read { path, source })?,
+            ),
+            Err(_) => None,
+        };
+        Self::from_sources(file.as_deref(), |key| std::env::var(key).ok())
+    }
+
+    pub fn from_sources(
+        file: Option<&str>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let mut config: Config = match file {
+            Some(contents) => toml::from_str(contents)?,
+            None => Config::default(),
+        };
+
+        if let Some(value) = env("ENVIRONMENT") {
+            config.environment = parse("ENVIRONMENT", value)?;
+        }
+        if let Some(value) = env("PORT") {
+            config.port = value.parse().map_err(|_| ConfigError::Invalid {
+                key: "PORT",
+                value,
+                reason: "expected a port number",
+            })?;
+        }
+        if let Some(value) = env("STORAGE") {
+            config.storage = parse("STORAGE", value)?;
+        }
+        if let Some(value) = env("MONGODB") {
+            config.database_uri = Some(value);
+        }
+        if let Some(value) = env("DATABASE_NAME") {
+            config.database_name = value;
+        }
+        if let Some(value) = env("SEARCH") {
+            config.search = parse("SEARCH", value)?;
+        }
+        if let Some(value) = env("PRODUCTS_FILE") {
+            config.products_file = Some(PathBuf::from(value));
+        }
+        if let Some(value) = env("COOKIE_DOMAIN") {
+            config.cookie_domain = value;
+        }
+        if let Some(value) = env("PUBLIC_URL") {
+            config.public_url = value;
+        }
+        if let Some(value) = env("IMAGE_DIR") {
+            config.image_dir = Some(PathBuf::from(value));
+        }
+        if let Some(value) = env("SESSION_REFRESH_FRACTION") {
+            config.session_refresh_fraction = value.parse().map_err(|_| ConfigError::Invalid {
+                key: "SESSION_REFRESH_FRACTION",
+                value,
+                reason: "expected a number",
+            })?;
+        }
+        if let Some(value) = env("METRICS_TOKEN") {
+            config.metrics_token = Some(value);
+        }
+        if let Some(value) = env("SMTP_HOST") {
+            config.mail.smtp_host = Some(value);
+        }
+        if let Some(value) = env("SMTP_USERNAME") {
+            config.mail.smtp_username = value;
+        }
+        if let Some(value) = env("SMTP_PASSWORD") {
+            config.mail.smtp_password = value;
+        }
+        if let Some(value) = env("MAIL_FROM") {
+            config.mail.from = value;
+        }
+        if let Some(value) = env("MAIL_DIR") {
+            config.mail.dir = PathBuf::from(value);
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.port == 0 {
+            return Err(ConfigError::Invalid {
+                key: "port",
+                value: self.port.to_string(),
+                reason: "must be greater than zero",
+            });
+        }
+        if self.storage == Storage::Mongo && self.database_uri.is_none() {
+            return Err(ConfigError::Missing("database URI (MONGODB)"));
+        }
+        if self.database_name.trim().is_empty() {
+            return Err(ConfigError::Missing("database name (DATABASE_NAME)"));
+        }
+        if self.cookie_domain.is_empty()
+            || self.cookie_domain.contains("://")
+            || self.cookie_domain.contains('/')
+        {
+            return Err(ConfigError::Invalid {
+                key: "cookie_domain",
+                value: self.cookie_domain.clone(),
+                reason: "expected a bare domain such as `snublejuice.no`",
+            });
+        }
+        if !(0.0..=1.0).contains(&self.session_refresh_fraction) {
+            return Err(ConfigError::Invalid {
+                key: "session_refresh_fraction",
+                value: self.session_refresh_fraction.to_string(),
+                reason: "must be between 0 and 1",
+            });
+        }
+        if !(self.public_url.starts_with("http://") || self.public_url.starts_with("https://")) {
+            return Err(ConfigError::Invalid {
+                key: "public_url",
+                value: self.public_url.clone(),
+                reason: "expected an absolute http(s) URL",
+            });
+        }
+        Ok(())
+    }
+
+    pub fn is_production(&self) -> bool {
+        self.environment == Environment::Production
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    #[test]
+    fn environment_overrides_file() {
+        let file = r#"
+            environment = "production"
+            port = 8080
+            database_uri = "mongodb://file"
+            cookie_domain = "staging.snublejuice.no"
+
+            [mail]
+            smtp_host = "smtp.example.com"
+        "#;
+        let config = Config::from_sources(
+            Some(file),
+            env(&[("PORT", "9000"), ("DATABASE_NAME", "staging")]),
+        )
+        .unwrap();
+
+        assert!(config.is_production());
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.database_uri.as_deref(), Some("mongodb://file"));
+        assert_eq!(config.database_name, "staging");
+        assert_eq!(config.search, Search::Atlas);
+        assert_eq!(config.cookie_domain, "staging.snublejuice.no");
+        assert_eq!(config.mail.smtp_host.as_deref(), Some("smtp.example.com"));
+        assert_eq!(config.mail.dir, PathBuf::from("mail"));
+    }
+
+    #[test]
+    fn memory_storage_needs_no_database_uri() {
+        let config =
+            Config::from_sources(None, env(&[("STORAGE", "memory"), ("SEARCH", "local")])).unwrap();
+        assert_eq!(config.search, Search::Local);
+        assert_eq!(config.storage, Storage::Memory);
+        assert_eq!(config.port, 3000);
+        assert!(!config.is_production());
+    }
+
+    #[test]
+    fn staging_is_not_production() {
+        let vars = [("STORAGE", "memory"), ("ENVIRONMENT", "staging")];
+        let config = Config::from_sources(None, env(&vars)).unwrap();
+        assert_eq!(config.environment, Environment::Staging);
+        assert!(!config.is_production());
+
+        let file = r#"environment = "staging""#;
+        let config = Config::from_sources(Some(file), env(&[("STORAGE", "memory")])).unwrap();
+        assert_eq!(config.environment, Environment::Staging);
+    }
+
+    #[test]
+    fn invalid_values_are_reported() {
+        let error = Config::from_sources(None, env(&[("MONGODB", "x"), ("PORT", "http")]))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            "Invalid value \"http\" for PORT: expected a port number"
+        );
+
+        assert!(matches!(
+            Config::from_sources(None, env(&[])),
+            Err(ConfigError::Missing(_))
+        ));
+        assert!(matches!(
+            Config::from_sources(None, env(&[("STORAGE", "redis")])),
+            Err(ConfigError::Invalid { key: "STORAGE", .. })
+        ));
+        assert!(matches!(
+            Config::from_sources(
+                None,
+                env(&[
+                    ("STORAGE", "memory"),
+                    ("COOKIE_DOMAIN", "https://snublejuice.no")
+                ])
+            ),
+            Err(ConfigError::Invalid {
+                key: "cookie_domain",
+                ..
+            })
+        ));
+        assert!(matches!(
+            Config::from_sources(Some("prot = 3000"), env(&[("STORAGE", "memory")])),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+}
@@ -8,6 +8,8 @@ use thiserror::Error;
 pub enum AppError {
     #[error("Database error: {0}")]
     MongoError(#[from] mongodb::error::Error),
+    #[error("Mail error: {0}")]
+    MailError(String),
     #[error("Not found")]
     NotFound,
     #[error("Internal server error")]
@@ -22,8 +24,16 @@ pub enum AppError {
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        // The client only sees a generic message, so keep the cause in the logs.
+        if matches!(
+            self,
+            AppError::MongoError(_) | AppError::MailError(_) | AppError::InternalServerError
+        ) {
+            tracing::error!(error = %self, "Request failed");
+        }
+
         let (status, error_message) = match self {
-            AppError::MongoError(_) | AppError::InternalServerError => {
+            AppError::MongoError(_) | AppError::MailError(_) | AppError::InternalServerError => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
             }
             AppError::NotFound => (StatusCode::NOT_FOUND, "Not found"),
@@ -0,0 +1,150 @@
+use chrono::{Datelike, Months, NaiveDate};
+use serde::Serialize;
+
+use crate::models::Product;
+
+#[derive(Debug, Serialize)]
+pub struct PricePoint {
+    pub month: String,
+    pub price: f64,
+}
+
+/// A fall in price between consecutive months. Both `amount` and `percentage` are positive, and
+/// measure the drop relative to the earlier price.
+#[derive(Debug, Serialize)]
+pub struct PriceDrop {
+    pub from: String,
+    pub to: String,
+    pub amount: f64,
+    pub percentage: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PriceHistory {
+    pub index: usize,
+    pub series: Vec<PricePoint>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub average: Option<f64>,
+    pub largest_drop: Option<PriceDrop>,
+}
+
+impl PriceHistory {
+    /// The last price in `Product.prices` belongs to the month of the latest update, i.e. the
+    /// current month unless the product has not yet been updated this month.
+    pub fn from_product(product: &Product, today: NaiveDate) -> Self {
+        let latest = if product.updated == Some(false) {
+            today - Months::new(1)
+        } else {
+            today
+        };
+        Self::from_prices(product.index, &product.prices, latest)
+    }
+
+    /// Prices of zero or below mark months without a price, and are left out of the series and
+    /// the figures derived from it.
+    pub fn from_prices(index: usize, prices: &[f64], latest: NaiveDate) -> Self {
+        let latest = latest.with_day(1).unwrap_or(latest);
+        let months: Vec<PricePoint> = prices
+            .iter()
+            .enumerate()
+            .map(|(i, &price)| PricePoint {
+                month: (latest - Months::new((prices.len() - 1 - i) as u32))
+                    .format("%Y-%m")
+                    .to_string(),
+                price,
+            })
+            .collect();
+
+        // A drop is only measured between consecutive months that both have a price.
+        let largest_drop = months
+            .windows(2)
+            .filter(|pair| pair[1].price > 0.0 && pair[1].price < pair[0].price)
+            .max_by(|a, b| {
+                (a[0].price - a[1].price)
+                    .partial_cmp(&(b[0].price - b[1].price))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .map(|pair| PriceDrop {
+                from: pair[0].month.clone(),
+                to: pair[1].month.clone(),
+                amount: pair[0].price - pair[1].price,
+                percentage: 100.0 * (pair[0].price - pair[1].price) / pair[0].price,
+            });
+
+        let series: Vec<PricePoint> = months
+            .into_iter()
+            .filter(|point| point.price > 0.0)
+            .collect();
+        let priced = || series.iter().map(|point| point.price);
+        let min = priced().reduce(f64::min);
+        let max = priced().reduce(f64::max);
+        let average = (!series.is_empty()).then(|| priced().sum::<f64>() / series.len() as f64);
+
+        PriceHistory {
+            index,
+            series,
+            min,
+            max,
+            average,
+            largest_drop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn from_prices_assigns_months_backwards_from_latest() {
+        let history = PriceHistory::from_prices(1, &[100.0, 120.0, 90.0], date(2025, 2, 17));
+        let months: Vec<&str> = history.series.iter().map(|p| p.month.as_str()).collect();
+        assert_eq!(months, vec!["2024-12", "2025-01", "2025-02"]);
+        assert_eq!(history.min, Some(90.0));
+        assert_eq!(history.max, Some(120.0));
+        assert!((history.average.unwrap() - 310.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn from_prices_finds_largest_drop_between_consecutive_months() {
+        let history = PriceHistory::from_prices(1, &[200.0, 180.0, 190.0, 140.0], date(2025, 4, 1));
+        let drop = history.largest_drop.unwrap();
+        assert_eq!(drop.from, "2025-03");
+        assert_eq!(drop.to, "2025-04");
+        assert!((drop.amount - 50.0).abs() < 1e-9);
+        assert!((drop.percentage - (50.0 / 190.0 * 100.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn from_prices_treats_unpriced_months_as_missing() {
+        let history = PriceHistory::from_prices(1, &[0.0, 150.0, 120.0], date(2025, 3, 1));
+        let drop = history.largest_drop.unwrap();
+        assert_eq!(drop.from, "2025-02");
+        assert!((drop.percentage - 20.0).abs() < 1e-9);
+
+        let unpriced = PriceHistory::from_prices(1, &[100.0, 0.0, 80.0, -1.0], date(2025, 4, 1));
+        assert!(unpriced.largest_drop.is_none());
+        let months: Vec<&str> = unpriced.series.iter().map(|p| p.month.as_str()).collect();
+        assert_eq!(months, vec!["2025-01", "2025-03"]);
+        assert_eq!(unpriced.min, Some(80.0));
+        assert_eq!(unpriced.max, Some(100.0));
+        assert_eq!(unpriced.average, Some(90.0));
+    }
+
+    #[test]
+    fn from_prices_handles_empty_and_rising_series() {
+        let empty = PriceHistory::from_prices(1, &[], date(2025, 1, 1));
+        assert!(empty.series.is_empty());
+        assert_eq!(empty.min, None);
+        assert_eq!(empty.average, None);
+        assert!(empty.largest_drop.is_none());
+
+        let rising = PriceHistory::from_prices(1, &[100.0, 110.0], date(2025, 1, 1));
+        assert!(rising.largest_drop.is_none());
+    }
+}
@@ -1,5 +1,9 @@
+pub mod config;
 pub mod errors;
+pub mod history;
+pub mod mail;
 pub mod models;
 pub mod query;
+pub mod repository;
 pub mod state;
 pub mod subdomain;
@@ -0,0 +1,16 @@
+use async_trait::async_trait;
+
+use crate::errors::AppError;
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivery of outgoing e-mail. Implementations live in the `notifications` crate.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: &Mail) -> Result<(), AppError>;
+}
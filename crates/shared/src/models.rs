@@ -1,8 +1,18 @@
 use mongodb::bson::{DateTime, oid::ObjectId};
 use serde::{Deserialize, Serialize};
 
+use crate::query::Parameters;
+
 pub const PRODUCTS_PER_PAGE: i64 = 15;
 pub const ONE_MONTH: u64 = 60 * 60 * 24 * 30;
+pub const MAX_SAVED_SEARCHES: u64 = 20;
+pub const MAX_SEARCH_MATCHES: i64 = 500;
+pub const MAX_FAVOURITE_LISTS: usize = 20;
+pub const MAX_LIST_ENTRIES: usize = 200;
+pub const MAX_ALERTS: u64 = 50;
+pub const MAX_NOTE_LENGTH: usize = 500;
+pub const RESET_TOKEN_LIFETIME: u64 = 60 * 60;
+pub const VERIFY_TOKEN_LIFETIME: u64 = 60 * 60 * 24 * 7;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct User {
@@ -13,14 +23,196 @@ pub struct User {
     pub email: String,
     pub favourites: Vec<i64>,
     pub notify: bool,
+    /// Whether the user has confirmed owning `email`. Mail is only sent to verified addresses.
+    /// Accounts from before addresses were verified are unverified, and are sent a link by
+    /// `authentication::auth::ask_existing_users_to_verify`.
+    #[serde(default)]
+    pub verified: bool,
+    /// Named lists in addition to the unnamed `favourites`, which the star toggle edits.
+    #[serde(default)]
+    pub lists: Vec<FavouriteList>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl User {
+    pub fn list(&self, id: &str) -> Option<&FavouriteList> {
+        self.lists.iter().find(|list| list.id == id)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FavouriteList {
+    /// Hex-encoded `ObjectId`, unique among the user's lists.
+    pub id: String,
+    pub name: String,
+    pub entries: Vec<FavouriteEntry>,
+    /// Public, unguessable address of a published list, i.e. `/liste/{slug}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slug: Option<String>,
+}
+
+impl FavouriteList {
+    pub fn indices(&self) -> Vec<i64> {
+        self.entries.iter().map(|entry| entry.index).collect()
+    }
+
+    pub fn entry(&self, index: i64) -> Option<&FavouriteEntry> {
+        self.entries.iter().find(|entry| entry.index == index)
+    }
+
+    /// Adds `entry`, replacing any existing entry for the same product.
+    pub fn upsert(&mut self, entry: FavouriteEntry) {
+        match self.entries.iter_mut().find(|e| e.index == entry.index) {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+    }
+
+    pub fn remove(&mut self, index: i64) -> Option<FavouriteEntry> {
+        let position = self.entries.iter().position(|entry| entry.index == index)?;
+        Some(self.entries.remove(position))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FavouriteEntry {
+    pub index: i64,
+    #[serde(default)]
+    pub note: Option<String>,
+    #[serde(rename = "targetPrice", default)]
+    pub target_price: Option<f64>,
+    pub added: DateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Session {
+    /// Identifies the session towards the user; `session_id` is the secret kept in the cookie.
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
     pub user_id: ObjectId,
     pub session_id: String,
     #[serde(rename = "expiresAfter")]
     pub expires_after: DateTime,
+    // Sessions created before devices were tracked lack the fields below.
+    #[serde(rename = "userAgent", default)]
+    pub user_agent: Option<String>,
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(rename = "createdAt", default)]
+    pub created_at: Option<DateTime>,
+    /// Written along with the sliding expiry, so only as fresh as the refresh throttle allows.
+    #[serde(rename = "lastSeen", default)]
+    pub last_seen: Option<DateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SavedSearch {
+    #[serde(rename = "_id")]
+    pub search_id: ObjectId,
+    pub user_id: ObjectId,
+    pub name: String,
+    pub subdomain: String,
+    pub parameters: Parameters,
+    /// Products matching the query at the last run.
+    pub matches: Vec<i64>,
+    /// Products that started matching at the last run.
+    pub fresh: Vec<i64>,
+    pub checked: DateTime,
+}
+
+/// The price an alert watches. `Taxfree` is `taxfree.price`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertField {
+    Price,
+    Literprice,
+    Taxfree,
+}
+
+impl AlertField {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertField::Price => "price",
+            AlertField::Literprice => "literprice",
+            AlertField::Taxfree => "taxfree",
+        }
+    }
+
+    /// The fields whose prices change with the monthly update of `subdomain`.
+    pub fn for_subdomain(subdomain: &str) -> &'static [AlertField] {
+        match subdomain {
+            "taxfree" => &[AlertField::Taxfree],
+            _ => &[AlertField::Price, AlertField::Literprice],
+        }
+    }
+
+    pub fn value(&self, product: &Product) -> Option<f64> {
+        let value = match self {
+            AlertField::Price => product.price,
+            AlertField::Literprice => product.literprice,
+            AlertField::Taxfree => product.taxfree.as_ref()?.price,
+        };
+        (value > 0.0).then_some(value)
+    }
+}
+
+/// Notifies a user once the price of a product falls to or below `threshold`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Alert {
+    #[serde(rename = "_id")]
+    pub alert_id: ObjectId,
+    pub user_id: ObjectId,
+    pub index: i64,
+    pub field: AlertField,
+    pub threshold: f64,
+    /// The price at the last evaluation, so that the alert only fires when crossing `threshold`.
+    pub value: Option<f64>,
+    pub triggered: Option<DateTime>,
+    /// Fired, but not yet seen by the user.
+    pub pending: bool,
+    pub created: DateTime,
+}
+
+impl Alert {
+    fn is_below(&self, value: Option<f64>) -> bool {
+        value.is_some_and(|value| value <= self.threshold)
+    }
+
+    /// Records `value` as the latest price, and returns whether the alert fired.
+    pub fn evaluate(&mut self, value: Option<f64>) -> bool {
+        let fired = self.is_below(value) && !self.is_below(self.value);
+        if fired {
+            self.triggered = Some(DateTime::now());
+            self.pending = true;
+        }
+        self.value = value;
+        fired
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenKind {
+    Reset,
+    Verify,
+}
+
+impl TokenKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenKind::Reset => "reset",
+            TokenKind::Verify => "verify",
+        }
+    }
+}
+
+/// A single-use token e-mailed to a user. Only its SHA-256 hash is stored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Token {
+    pub user_id: ObjectId,
+    pub kind: TokenKind,
+    pub hash: String,
+    #[serde(rename = "expiresAfter")]
+    pub expires_after: DateTime,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -33,13 +225,13 @@ pub struct Notify {
     pub notify: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Aperitif {
     pub url: String,
     pub points: i32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Taxfree {
     pub url: String,
     pub price: f64,
@@ -47,19 +239,19 @@ pub struct Taxfree {
     pub stores: Vec<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Clone)]
 pub struct Characteristic {
     pub name: String,
     pub percentage: u32,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Clone)]
 pub struct Ingredient {
     pub grape: String,
     pub percentage: f64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Product {
     pub index: usize,
     pub name: String,
@@ -96,6 +288,14 @@ pub struct Product {
     pub taxfree: Option<Taxfree>,
 }
 
+/// One page of a listing, along with the number of matches across every page.
+#[derive(Debug, Serialize, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: u64,
+    pub pages: u64,
+}
+
 fn deserialize_characteristics<'de, D>(deserializer: D) -> Result<Vec<Characteristic>, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -118,7 +318,7 @@ where
             })
         })
         .collect();
-    v.sort_by(|a, b| b.percentage.cmp(&a.percentage));
+    v.sort_by_key(|c| std::cmp::Reverse(c.percentage));
     Ok(v)
 }
 
@@ -185,6 +385,47 @@ mod tests {
     use super::*;
     use serde_json::json;
 
+    #[test]
+    fn alert_fires_once_when_crossing_threshold() {
+        let mut alert = Alert {
+            alert_id: ObjectId::new(),
+            user_id: ObjectId::new(),
+            index: 1,
+            field: AlertField::Price,
+            threshold: 250.0,
+            value: Some(300.0),
+            triggered: None,
+            pending: false,
+            created: DateTime::now(),
+        };
+
+        assert!(!alert.evaluate(Some(260.0)));
+        assert!(alert.evaluate(Some(250.0)));
+        assert!(alert.pending && alert.triggered.is_some());
+
+        alert.pending = false;
+        assert!(!alert.evaluate(Some(240.0)));
+        assert!(!alert.evaluate(None));
+        assert!(alert.evaluate(Some(200.0)));
+        assert!(!alert.evaluate(Some(280.0)));
+        assert!(alert.evaluate(Some(230.0)));
+    }
+
+    #[test]
+    fn users_from_before_verification_are_unverified() {
+        let user: User = serde_json::from_value(json!({
+            "_id": { "$oid": "65a1f0c2e4b0a1b2c3d4e5f6" },
+            "username": "snubler",
+            "password": "hash",
+            "email": "snubler@example.com",
+            "favourites": [1],
+            "notify": true,
+        }))
+        .unwrap();
+        assert!(!user.verified);
+        assert!(user.lists.is_empty());
+    }
+
     #[test]
     fn deserializes_characteristics_into_percentages() {
         let product: Product = serde_json::from_value(json!({
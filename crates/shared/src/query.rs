@@ -2,15 +2,23 @@ use mongodb::bson::{Bson, Document, doc};
 use regex;
 use serde::{Deserialize, Serialize};
 
-use crate::models::{PRODUCTS_PER_PAGE, User};
+use crate::models::{AlertField, PRODUCTS_PER_PAGE, User};
 use crate::subdomain::Subdomain;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The `sort` of search results ranked by how well they match, which is the default for searches.
+pub const RELEVANCE: &str = "relevance";
+
+/// The highest page whose `$skip` does not overflow.
+const MAX_PAGE: i64 = i64::MAX / PRODUCTS_PER_PAGE;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Parameters {
     pub page: Option<i64>,
     pub sort: Option<String>,
     pub ascending: Option<bool>,
     pub favourites: Option<bool>,
+    /// Restricts `favourites` to one of the user's named lists.
+    pub list: Option<String>,
     pub category: Option<String>,
     pub country: Option<String>,
     pub price: Option<f64>,
@@ -35,6 +43,7 @@ impl Parameters {
             && self.sort.is_none()
             && self.ascending.is_none()
             && self.favourites.is_none()
+            && self.list.is_none()
             && self.category.is_none()
             && self.country.is_none()
             && self.price.is_none()
@@ -51,8 +60,32 @@ impl Parameters {
             && self.store_taxfree.is_none()
     }
 
+    /// The requested page, counting from 1. Pages past the listing are moved back by `clamp_page`
+    /// once the matches are counted.
+    pub fn page(&self) -> i64 {
+        self.page.unwrap_or(1).clamp(1, MAX_PAGE)
+    }
+
+    /// Moves `page` within the `pages` of the listing. Returns whether it was past the last page,
+    /// in which case the products fetched for it were none and should be fetched again.
+    pub fn clamp_page(&mut self, pages: u64) -> bool {
+        let last = pages.max(1) as i64;
+        let past = self.page() > last;
+        if self.page.is_some() {
+            self.page = Some(self.page().min(last));
+        }
+        past
+    }
+
+    /// Whether the results are left in the order ranked by the search.
+    pub fn sorts_by_relevance(&self) -> bool {
+        self.search.is_some() && matches!(self.sort.as_deref(), None | Some(RELEVANCE))
+    }
+
     fn get_sort_by(&self, subdomain: &Subdomain) -> String {
-        if let Some(sort) = &self.sort {
+        if let Some(sort) = &self.sort
+            && sort != RELEVANCE
+        {
             if subdomain.is_taxfree() && sort != "alcohol" {
                 format!("taxfree.{}", sort)
             } else if sort == "rating" {
@@ -87,29 +120,37 @@ impl Parameters {
         // Must have a price.
         filter.insert("price", doc! { "$gt": 0.0 });
 
-        if let Some(storelike) = &self.storelike {
-            if !favourites && !taxfree {
-                let pattern = format!(
-                    r"(^|[^a-zæøåA-ZÆØÅ]){}([^a-zæøåA-ZÆØÅ]|$)",
-                    regex::escape(storelike)
-                );
-                filter.insert("stores", doc! { "$regex": pattern, "$options": "i" });
-            }
+        if let Some(storelike) = &self.storelike
+            && !favourites
+            && !taxfree
+        {
+            let pattern = format!(
+                r"(^|[^a-zæøåA-ZÆØÅ]){}([^a-zæøåA-ZÆØÅ]|$)",
+                regex::escape(storelike)
+            );
+            filter.insert("stores", doc! { "$regex": pattern, "$options": "i" });
         }
 
         if favourites && let Some(user) = user {
-            filter.insert("index", doc! { "$in": user.favourites.clone() });
+            let indices = match &self.list {
+                Some(id) => user.list(id).map(|list| list.indices()).unwrap_or_default(),
+                None => user.favourites.clone(),
+            };
+            filter.insert("index", doc! { "$in": indices });
         }
 
         // Early return for searches.
-        if let Some(_) = &self.search {
+        if self.search.is_some() {
+            if !self.sorts_by_relevance() {
+                self.require_sort_field(&mut filter, subdomain);
+            }
             return filter;
         }
 
-        if let Some(category) = &self.category {
-            if let Some(name) = category_name(category) {
-                filter.insert("category", name);
-            }
+        if let Some(category) = &self.category
+            && let Some(name) = category_name(category)
+        {
+            filter.insert("category", name);
         }
 
         if let Some(country) = &self.country {
@@ -180,35 +221,31 @@ impl Parameters {
             );
         }
 
-        // Sort field must exist and be non-null (skip if already constrained above).
+        self.require_sort_field(&mut filter, subdomain);
+
+        filter
+    }
+
+    /// Sort field must exist and be non-null (skip if already constrained).
+    fn require_sort_field(&self, filter: &mut Document, subdomain: &Subdomain) {
         let sort_by = self.get_sort_by(subdomain);
         if !filter.contains_key(&sort_by) {
             let is_discount = sort_by == "discount" || sort_by == "taxfree.discount";
             filter.insert(sort_by, doc! { "$exists": true, "$ne": Bson::Null, "$gt": if is_discount {-100.0} else {0.0}  });
         }
-
-        filter
-    }
-
-    pub fn to_options(&self, subdomain: &Subdomain) -> Vec<Document> {
-        let mut options = Vec::new();
-
-        options.push(
-            doc! {
-                "$sort": {self.get_sort_by(subdomain): if self.ascending == Some(false) { -1 } else { 1 }}
-            }
-        );
-        options.push(doc! { "$skip": ((self.page.unwrap_or(1) - 1) * PRODUCTS_PER_PAGE) });
-        options.push(doc! { "$limit": PRODUCTS_PER_PAGE });
-
-        options
     }
 
-    pub fn to_pipeline(&self, subdomain: &Subdomain, user: &Option<User>, prices_updated: bool) -> Vec<Document> {
-        let mut pipeline: Vec<Document> = Vec::new();
+    /// The stages selecting the matching products, before they are sorted and paginated.
+    pub fn to_query(
+        &self,
+        subdomain: &Subdomain,
+        user: &Option<User>,
+        prices_updated: bool,
+    ) -> Vec<Document> {
+        let mut query: Vec<Document> = Vec::new();
 
         if let Some(search) = &self.search {
-            pipeline.push(doc! {
+            query.push(doc! {
                 "$search": {
                     "index": "name",
                     "compound": {
@@ -235,19 +272,42 @@ impl Parameters {
                     },
                 },
             });
+        }
 
-            pipeline.push(doc! { "$match": self.to_filter(subdomain, user, prices_updated) });
+        query.push(doc! { "$match": self.to_filter(subdomain, user, prices_updated) });
 
-            pipeline.push(doc! { "$skip": ((self.page.unwrap_or(1) - 1) * PRODUCTS_PER_PAGE) });
-            pipeline.push(doc! { "$limit": PRODUCTS_PER_PAGE });
+        query
+    }
 
-            return pipeline;
+    /// The stages picking the requested page from the products selected by `to_query`. Search
+    /// results keep their relevance order unless another `sort` is chosen.
+    pub fn to_paging(&self, subdomain: &Subdomain) -> Vec<Document> {
+        if self.sorts_by_relevance() {
+            return vec![
+                doc! { "$skip": ((self.page() - 1) * PRODUCTS_PER_PAGE) },
+                doc! { "$limit": PRODUCTS_PER_PAGE },
+            ];
         }
+        self.to_options(subdomain)
+    }
 
-        pipeline.push(doc! { "$match": self.to_filter(subdomain, user, prices_updated) });
+    pub fn to_options(&self, subdomain: &Subdomain) -> Vec<Document> {
+        let mut options = Vec::new();
+
+        options.push(
+            doc! {
+                "$sort": {self.get_sort_by(subdomain): if self.ascending == Some(false) { -1 } else { 1 }}
+            }
+        );
+        options.push(doc! { "$skip": ((self.page() - 1) * PRODUCTS_PER_PAGE) });
+        options.push(doc! { "$limit": PRODUCTS_PER_PAGE });
 
-        pipeline.extend(self.to_options(subdomain));
+        options
+    }
 
+    pub fn to_pipeline(&self, subdomain: &Subdomain, user: &Option<User>, prices_updated: bool) -> Vec<Document> {
+        let mut pipeline = self.to_query(subdomain, user, prices_updated);
+        pipeline.extend(self.to_paging(subdomain));
         pipeline
     }
 }
@@ -278,11 +338,77 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+#[derive(Deserialize)]
+pub struct ForgotRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetRequest {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyRequest {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    pub password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangeUsernameRequest {
+    pub password: String,
+    pub username: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangeEmailRequest {
+    pub password: String,
+    pub email: String,
+}
+
 #[derive(Deserialize)]
 pub struct DeleteRequest {
     pub password: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct SavedSearchRequest {
+    pub name: String,
+    pub parameters: Parameters,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AlertRequest {
+    pub index: i64,
+    pub field: AlertField,
+    pub threshold: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListRequest {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListEntryRequest {
+    pub index: i64,
+    pub note: Option<String>,
+    pub target_price: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MoveEntryRequest {
+    pub index: i64,
+    /// Id of the list to move the entry to.
+    pub to: String,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct SignupRequest {
     pub username: String,
@@ -295,8 +421,8 @@ pub struct SignupRequest {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::User;
-    use mongodb::bson::oid::ObjectId;
+    use crate::models::{FavouriteEntry, FavouriteList, User};
+    use mongodb::bson::{DateTime, oid::ObjectId};
 
     fn empty_params() -> Parameters {
         Parameters {
@@ -304,6 +430,7 @@ mod tests {
             sort: None,
             ascending: None,
             favourites: None,
+            list: None,
             category: None,
             country: None,
             price: None,
@@ -329,6 +456,8 @@ mod tests {
             email: "test@example.com".to_string(),
             favourites,
             notify: false,
+            verified: true,
+            lists: vec![],
         }
     }
 
@@ -381,6 +510,36 @@ mod tests {
         assert!(!filter.contains_key("category"));
     }
 
+    #[test]
+    fn search_sorts_by_relevance_unless_another_sort_is_chosen() {
+        let subdomain = Subdomain::Vinmonopolet;
+        let mut params = empty_params();
+        params.search = Some("cabernet".to_string());
+        assert!(params.sorts_by_relevance());
+        let filter = params.to_filter(&subdomain, &None, true);
+        assert!(!filter.contains_key("discount"));
+        let paging = params.to_paging(&subdomain);
+        assert!(paging.iter().all(|stage| !stage.contains_key("$sort")));
+
+        params.sort = Some(RELEVANCE.to_string());
+        assert!(params.sorts_by_relevance());
+
+        params.sort = Some("literprice".to_string());
+        params.ascending = Some(false);
+        assert!(!params.sorts_by_relevance());
+        let paging = params.to_paging(&subdomain);
+        assert_eq!(paging[0], doc! { "$sort": { "literprice": -1 } });
+        let filter = params.to_filter(&subdomain, &None, true);
+        assert!(filter.contains_key("literprice"));
+
+        // Without a search there is nothing to rank, so the usual default applies.
+        params.search = None;
+        params.sort = Some(RELEVANCE.to_string());
+        assert!(!params.sorts_by_relevance());
+        let paging = params.to_paging(&subdomain);
+        assert_eq!(paging[0], doc! { "$sort": { "discount": -1 } });
+    }
+
     #[test]
     fn to_filter_favourites_restricts_to_user_indices() {
         let mut params = empty_params();
@@ -393,6 +552,46 @@ mod tests {
         );
     }
 
+    #[test]
+    fn to_filter_favourites_restricts_to_chosen_list() {
+        let mut params = empty_params();
+        params.favourites = Some(true);
+        params.list = Some("julebord".to_string());
+        let mut user = test_user(vec![1, 2, 3]);
+        user.lists.push(FavouriteList {
+            id: "julebord".to_string(),
+            name: "Julebord".to_string(),
+            entries: vec![FavouriteEntry {
+                index: 9,
+                note: None,
+                target_price: None,
+                added: DateTime::now(),
+            }],
+            slug: None,
+        });
+        let filter = params.to_filter(&Subdomain::Vinmonopolet, &Some(user.clone()), true);
+        assert_eq!(
+            filter
+                .get_document("index")
+                .unwrap()
+                .get_array("$in")
+                .unwrap(),
+            &vec![Bson::Int64(9)]
+        );
+
+        // An unknown list matches nothing rather than falling back to every product.
+        params.list = Some("kjeller".to_string());
+        let filter = params.to_filter(&Subdomain::Vinmonopolet, &Some(user), true);
+        assert!(
+            filter
+                .get_document("index")
+                .unwrap()
+                .get_array("$in")
+                .unwrap()
+                .is_empty()
+        );
+    }
+
     #[test]
     fn to_options_sorts_and_paginates() {
         let mut params = empty_params();
@@ -432,4 +631,52 @@ mod tests {
         assert!(pipeline[2].contains_key("$skip"));
         assert!(pipeline[3].contains_key("$limit"));
     }
+
+    #[test]
+    fn clamp_page_keeps_page_within_listing() {
+        let mut params = empty_params();
+        assert!(!params.clamp_page(3));
+        assert_eq!((params.page, params.page()), (None, 1));
+
+        params.page = Some(-2);
+        assert_eq!(params.page(), 1);
+        assert!(!params.clamp_page(3));
+        assert_eq!(params.page, Some(1));
+
+        params.page = Some(7);
+        assert!(params.clamp_page(3));
+        assert_eq!(params.page, Some(3));
+        assert!(!params.clamp_page(3));
+
+        params.page = Some(2);
+        assert!(params.clamp_page(0));
+        assert_eq!(params.page, Some(1));
+
+        params.page = Some(i64::MAX);
+        let skip = params.to_options(&Subdomain::Vinmonopolet)[1].get_i64("$skip");
+        assert!(skip.is_ok_and(|skip| skip > 0));
+        assert!(params.clamp_page(3));
+        assert_eq!(params.page, Some(3));
+    }
+
+    #[test]
+    fn to_pipeline_is_query_followed_by_paging() {
+        let mut params = empty_params();
+        params.page = Some(2);
+        let subdomain = Subdomain::Vinmonopolet;
+        let query = params.to_query(&subdomain, &None, true);
+        assert_eq!(query.len(), 1);
+        assert!(query[0].contains_key("$match"));
+        assert_eq!(params.to_paging(&subdomain), params.to_options(&subdomain));
+
+        params.search = Some("riesling".to_string());
+        let query = params.to_query(&subdomain, &None, true);
+        assert!(query[0].contains_key("$search"));
+        let paging = params.to_paging(&subdomain);
+        assert!(paging.iter().all(|stage| !stage.contains_key("$sort")));
+        assert_eq!(
+            params.to_pipeline(&subdomain, &None, true),
+            [query, paging].concat()
+        );
+    }
 }
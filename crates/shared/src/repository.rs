@@ -0,0 +1,290 @@
+use async_trait::async_trait;
+use mongodb::bson::{Bson, Document, doc, oid::ObjectId};
+use std::collections::HashMap;
+
+use crate::{
+    errors::AppError,
+    models::{
+        Alert, AlertField, FavouriteEntry, FavouriteList, Page, Product, SavedSearch, Session,
+        Token, TokenKind, User,
+    },
+};
+
+/// Read access to the product catalogue. Pipelines and filters are the documents produced by
+/// `Parameters::to_pipeline` and `Parameters::to_filter`.
+#[async_trait]
+pub trait ProductRepository: Send + Sync {
+    async fn get_products(&self, pipeline: Vec<Document>) -> Result<Vec<Product>, AppError>;
+
+    async fn get_indices(&self, pipeline: Vec<Document>) -> Result<Vec<i64>, AppError>;
+
+    /// The products selected by `query` and arranged by `paging`, counted before `paging` applies.
+    /// The stages are those of `Parameters::to_query` and `Parameters::to_paging`.
+    async fn get_page(
+        &self,
+        query: Vec<Document>,
+        paging: Vec<Document>,
+    ) -> Result<Page<Product>, AppError>;
+
+    async fn get_distinct(&self, field: &str, is_taxfree: bool) -> Vec<String>;
+
+    async fn get_product(&self, index: i64) -> Option<Product> {
+        self.get_products(vec![
+            doc! { "$match": { "index": index } },
+            doc! { "$limit": 1 },
+        ])
+        .await
+        .ok()?
+        .into_iter()
+        .next()
+    }
+
+    /// The products with `indices`. Products that no longer exist are left out, but a failed
+    /// lookup is an error, so that callers can tell the two apart.
+    async fn get_products_by_indices(&self, indices: &[i64]) -> Result<Vec<Product>, AppError> {
+        self.get_products(vec![doc! { "$match": { "index": { "$in": indices } } }])
+            .await
+    }
+
+    async fn get_preview(&self, taxfree: bool) -> Option<Product> {
+        let pipeline = if taxfree {
+            vec![
+                doc! { "$match": {
+                    "taxfree.stores": { "$exists": true, "$ne": Bson::Null },
+                    "taxfree.valid": true,
+                    "taxfree.discount": { "$lt": 0.0 },
+                }},
+                doc! { "$sort": { "taxfree.discount": 1 } },
+                doc! { "$limit": 1 },
+            ]
+        } else {
+            vec![
+                doc! { "$match": {
+                    "updated": true,
+                    "orderable": true,
+                    "discount": { "$lt": 0.0 },
+                    "price": { "$gt": 0.0 },
+                    "alcohol": { "$gt": 0.0 },
+                }},
+                doc! { "$sort": { "discount": 1 } },
+                doc! { "$limit": 1 },
+            ]
+        };
+        self.get_products(pipeline).await.ok()?.into_iter().next()
+    }
+}
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn get_user_by_name(&self, username: &str) -> Option<User>;
+
+    async fn get_user_by_id(&self, user_id: &ObjectId) -> Option<User>;
+
+    /// Addresses are compared case-insensitively.
+    async fn get_user_by_email(&self, email: &str) -> Option<User>;
+
+    /// The owner of the published list with `slug`.
+    async fn get_user_by_list_slug(&self, slug: &str) -> Option<User>;
+
+    /// Users that want notifications and have a verified address.
+    async fn get_notified_users(&self) -> Result<Vec<User>, AppError>;
+
+    async fn create_user(&self, user: &User) -> Result<(), AppError>;
+
+    async fn toggle_favourite(&self, user_id: &ObjectId, index: &i64) -> Result<(), AppError>;
+
+    async fn notification(&self, user_id: &ObjectId, notify: bool) -> Result<(), AppError>;
+
+    async fn update_password(&self, user_id: &ObjectId, password: &str) -> Result<(), AppError>;
+
+    async fn set_verified(&self, user_id: &ObjectId) -> Result<(), AppError>;
+
+    /// Claims a user that wants notifications but signed up before addresses were verified, by
+    /// marking it as unverified, so that each such user is only returned once.
+    async fn take_user_to_verify(&self) -> Result<Option<User>, AppError>;
+
+    async fn update_username(&self, user_id: &ObjectId, username: &str) -> Result<(), AppError>;
+
+    /// Adds a named favourite list, and returns whether it was added, which it is not once the
+    /// user has `MAX_FAVOURITE_LISTS` lists.
+    async fn create_list(&self, user_id: &ObjectId, list: &FavouriteList)
+    -> Result<bool, AppError>;
+
+    /// Returns the renamed list, or `None` if the user has no such list.
+    async fn rename_list(
+        &self,
+        user_id: &ObjectId,
+        list_id: &str,
+        name: &str,
+    ) -> Result<Option<FavouriteList>, AppError>;
+
+    /// Returns whether the user had the list.
+    async fn delete_list(&self, user_id: &ObjectId, list_id: &str) -> Result<bool, AppError>;
+
+    /// Publishes a list under `slug`, unless it is already published, and returns the list.
+    async fn share_list(
+        &self,
+        user_id: &ObjectId,
+        list_id: &str,
+        slug: &str,
+    ) -> Result<Option<FavouriteList>, AppError>;
+
+    /// Returns whether the user had the list.
+    async fn unshare_list(&self, user_id: &ObjectId, list_id: &str) -> Result<bool, AppError>;
+
+    /// Updates the note and target price of the entry for the same product, or adds `entry` if
+    /// the list has fewer than `MAX_LIST_ENTRIES` entries. Returns the list, or `None` if the
+    /// user has no such list or it is full.
+    async fn put_entry(
+        &self,
+        user_id: &ObjectId,
+        list_id: &str,
+        entry: &FavouriteEntry,
+    ) -> Result<Option<FavouriteList>, AppError>;
+
+    /// Returns whether the list had an entry for the product.
+    async fn remove_entry(
+        &self,
+        user_id: &ObjectId,
+        list_id: &str,
+        index: i64,
+    ) -> Result<bool, AppError>;
+
+    /// Moves `entry` from the list `from` to the list `to`, replacing any entry for the same
+    /// product there. Returns whether it was moved, which it is not if `from` no longer has the
+    /// entry or `to` is full.
+    async fn move_entry(
+        &self,
+        user_id: &ObjectId,
+        from: &str,
+        to: &str,
+        entry: &FavouriteEntry,
+    ) -> Result<bool, AppError>;
+
+    /// Changes the address and marks it as unverified.
+    async fn update_email(&self, user_id: &ObjectId, email: &str) -> Result<(), AppError>;
+
+    /// Deletes the user along with everything stored for it, except its sessions.
+    async fn delete_user(&self, user_id: &ObjectId) -> Result<(), AppError>;
+
+    async fn favourites(&self, user_id: &ObjectId) -> Result<Vec<i64>, AppError> {
+        match self.get_user_by_id(user_id).await {
+            Some(user) => Ok(user.favourites),
+            None => Err(AppError::NotFound),
+        }
+    }
+
+    async fn get_saved_searches(&self, user_id: &ObjectId) -> Result<Vec<SavedSearch>, AppError>;
+
+    async fn get_saved_searches_for_subdomain(
+        &self,
+        subdomain: &str,
+    ) -> Result<Vec<SavedSearch>, AppError>;
+
+    async fn count_saved_searches(&self, user_id: &ObjectId) -> Result<u64, AppError> {
+        Ok(self.get_saved_searches(user_id).await?.len() as u64)
+    }
+
+    async fn create_saved_search(&self, search: &SavedSearch) -> Result<(), AppError>;
+
+    async fn update_saved_search(&self, search: &SavedSearch) -> Result<(), AppError>;
+
+    async fn record_search_matches(
+        &self,
+        search_id: &ObjectId,
+        matches: &[i64],
+        fresh: &[i64],
+    ) -> Result<(), AppError>;
+
+    async fn delete_saved_search(
+        &self,
+        user_id: &ObjectId,
+        search_id: &ObjectId,
+    ) -> Result<(), AppError>;
+
+    async fn get_alerts(&self, user_id: &ObjectId) -> Result<Vec<Alert>, AppError>;
+
+    /// Every user's alerts on any of `fields`, for evaluation after a price update.
+    async fn get_alerts_for_fields(&self, fields: &[AlertField]) -> Result<Vec<Alert>, AppError>;
+
+    async fn count_alerts(&self, user_id: &ObjectId) -> Result<u64, AppError> {
+        Ok(self.get_alerts(user_id).await?.len() as u64)
+    }
+
+    async fn create_alert(&self, alert: &Alert) -> Result<(), AppError>;
+
+    /// Stores the outcome of `Alert::evaluate`.
+    async fn record_alert(&self, alert: &Alert) -> Result<(), AppError>;
+
+    /// Marks all of the user's fired alerts as seen.
+    async fn dismiss_alerts(&self, user_id: &ObjectId) -> Result<(), AppError>;
+
+    async fn delete_alert(&self, user_id: &ObjectId, alert_id: &ObjectId) -> Result<(), AppError>;
+}
+
+#[async_trait]
+pub trait SessionRepository: Send + Sync {
+    async fn store_session(&self, session: Session) -> Result<(), AppError>;
+
+    async fn get_session(&self, session_id: &str) -> Result<Session, AppError>;
+
+    async fn get_sessions_for_user(&self, user_id: &ObjectId) -> Result<Vec<Session>, AppError>;
+
+    /// Slides the expiration of the sessions one month forward and marks them as seen now.
+    async fn update_expirations(&self, session_ids: &[String]) -> Result<(), AppError>;
+
+    async fn delete_session(&self, session_id: &str) -> Result<(), AppError>;
+
+    async fn delete_sessions_for_user(&self, user_id: &ObjectId) -> Result<(), AppError>;
+
+    /// Revokes one of the user's sessions by its public id.
+    async fn delete_session_by_id(&self, user_id: &ObjectId, id: &ObjectId)
+    -> Result<(), AppError>;
+
+    /// Revokes every session of the user except the one with `session_id`.
+    async fn delete_other_sessions(
+        &self,
+        user_id: &ObjectId,
+        session_id: &str,
+    ) -> Result<(), AppError>;
+
+    /// Removes sessions past their expiry, returning how many were removed.
+    async fn delete_expired_sessions(&self) -> Result<u64, AppError>;
+
+    /// Counts the sessions that have not yet expired.
+    async fn count_active_sessions(&self) -> Result<u64, AppError>;
+}
+
+#[async_trait]
+pub trait TokenRepository: Send + Sync {
+    async fn store_token(&self, token: Token) -> Result<(), AppError>;
+
+    /// Removes and returns the unexpired token with the given hash, so that it can be used once.
+    async fn consume_token(&self, kind: TokenKind, hash: &str) -> Result<Option<Token>, AppError>;
+
+    async fn delete_tokens_for_user(
+        &self,
+        user_id: &ObjectId,
+        kind: TokenKind,
+    ) -> Result<(), AppError>;
+
+    async fn delete_expired_tokens(&self) -> Result<u64, AppError>;
+}
+
+#[async_trait]
+pub trait MetadataRepository: Send + Sync {
+    async fn increment_visitor(&self, month: &str, subdomain: &str, fresh: bool);
+
+    async fn get_prices_updated(&self, subdomain: &str) -> bool;
+
+    /// Records the current price state of `subdomain` for `job`, and returns whether it just
+    /// flipped to updated, i.e. whether the job should run for the fresh monthly prices. The first
+    /// state recorded for a job never counts as a flip, and a state that cannot be read is not
+    /// recorded.
+    async fn prices_flipped(&self, job: &str, subdomain: &str) -> bool;
+
+    /// Prices of the products as seen by the previous notification run, keyed by index.
+    async fn get_price_snapshot(&self, subdomain: &str) -> HashMap<String, f64>;
+
+    async fn set_price_snapshot(&self, subdomain: &str, prices: &HashMap<String, f64>);
+}
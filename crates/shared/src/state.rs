@@ -1,13 +1,60 @@
-use axum::extract::FromRef;
-use mongodb::Database;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use crate::config::Config;
+use crate::mail::Mailer;
+use crate::repository::{
+    MetadataRepository, ProductRepository, SessionRepository, TokenRepository, UserRepository,
+};
 
 #[derive(Clone)]
 pub struct AppState {
-    pub db: Database,
+    pub products: Arc<dyn ProductRepository>,
+    pub users: Arc<dyn UserRepository>,
+    pub sessions: Arc<dyn SessionRepository>,
+    pub tokens: Arc<dyn TokenRepository>,
+    pub metadata: Arc<dyn MetadataRepository>,
+    pub mailer: Arc<dyn Mailer>,
+    pub config: Arc<Config>,
+    pub refreshes: Arc<PendingRefreshes>,
+}
+
+/// Sessions whose expiry should slide forward. `Authenticate` queues them, and
+/// `database::jobs::refresh_sessions` writes them in batches.
+#[derive(Default)]
+pub struct PendingRefreshes(Mutex<HashSet<String>>);
+
+impl PendingRefreshes {
+    pub fn push(&self, session_id: String) {
+        self.0.lock().unwrap().insert(session_id);
+    }
+
+    pub fn take(&self) -> Vec<String> {
+        self.0.lock().unwrap().drain().collect()
+    }
 }
 
-impl FromRef<AppState> for Database {
-    fn from_ref(state: &AppState) -> Self {
-        state.db.clone()
+impl AppState {
+    /// Uses the same store for every repository.
+    pub fn from_store<S>(store: S, config: Config, mailer: Arc<dyn Mailer>) -> Self
+    where
+        S: ProductRepository
+            + UserRepository
+            + SessionRepository
+            + TokenRepository
+            + MetadataRepository
+            + 'static,
+    {
+        let store = Arc::new(store);
+        AppState {
+            products: store.clone(),
+            users: store.clone(),
+            sessions: store.clone(),
+            tokens: store.clone(),
+            metadata: store,
+            mailer,
+            config: Arc::new(config),
+            refreshes: Arc::new(PendingRefreshes::default()),
+        }
     }
 }
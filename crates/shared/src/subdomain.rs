@@ -21,6 +21,19 @@ impl Subdomain {
             Self::Landing => "landing",
         }
     }
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "vinmonopolet" => Self::Vinmonopolet,
+            "taxfree" => Self::Taxfree,
+            _ => Self::Landing,
+        }
+    }
+
+    pub fn from_host(host: &str) -> Self {
+        let subdomain = host.split('.').next().unwrap_or("");
+        Self::from_name(&subdomain.to_lowercase())
+    }
 }
 
 pub fn landing_url_from_host(host: &str) -> String {
@@ -35,6 +48,16 @@ pub fn landing_url_from_host(host: &str) -> String {
     }
 }
 
+/// The address of `subdomain` under `public_url`, e.g. `https://taxfree.snublejuice.no` for
+/// `https://snublejuice.no`.
+pub fn subdomain_url(public_url: &str, subdomain: &Subdomain) -> String {
+    let public_url = public_url.trim_end_matches('/');
+    match public_url.split_once("://") {
+        Some((scheme, host)) => format!("{scheme}://{}.{host}", subdomain.name()),
+        None => format!("{}.{public_url}", subdomain.name()),
+    }
+}
+
 impl<S> FromRequestParts<S> for Subdomain
 where
     S: Send + Sync,
@@ -48,12 +71,7 @@ where
             .and_then(|h| h.to_str().ok())
             .ok_or_else(|| AppError::BadRequest("Missing Host header".to_string()))?;
 
-        let subdomain = host.split('.').next().unwrap_or("");
-        match subdomain.to_lowercase().as_str() {
-            "vinmonopolet" => Ok(Subdomain::Vinmonopolet),
-            "taxfree" => Ok(Subdomain::Taxfree),
-            _ => Ok(Subdomain::Landing),
-        }
+        Ok(Subdomain::from_host(host))
     }
 }
 
@@ -73,6 +91,24 @@ mod tests {
         assert!(!Subdomain::Landing.is_taxfree());
     }
 
+    #[test]
+    fn from_name_roundtrips_name() {
+        for subdomain in [
+            Subdomain::Vinmonopolet,
+            Subdomain::Taxfree,
+            Subdomain::Landing,
+        ] {
+            assert_eq!(
+                Subdomain::from_name(subdomain.name()).name(),
+                subdomain.name()
+            );
+        }
+        assert!(matches!(
+            Subdomain::from_name("unknown"),
+            Subdomain::Landing
+        ));
+    }
+
     #[test]
     fn landing_url_from_host_strips_subdomain() {
         assert_eq!(
@@ -86,6 +122,19 @@ mod tests {
         assert_eq!(landing_url_from_host("snublejuice.no"), "//no");
     }
 
+    #[test]
+    fn subdomain_url_prefixes_the_public_host() {
+        assert_eq!(
+            subdomain_url("https://snublejuice.no/", &Subdomain::Taxfree),
+            "https://taxfree.snublejuice.no"
+        );
+        let local = subdomain_url(
+            "http://snublejuice.localhost:3000",
+            &Subdomain::Vinmonopolet,
+        );
+        assert_eq!(local, "http://vinmonopolet.snublejuice.localhost:3000");
+    }
+
     #[tokio::test]
     async fn from_request_parts_parses_host_header() {
         async fn extract(host: &str) -> Subdomain {